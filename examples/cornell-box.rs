@@ -0,0 +1,99 @@
+use std::io;
+
+use aurora::{
+    bvh::BvhNode,
+    camera::{Background, CameraBuilder},
+    hittable::HittableList,
+    material::Material,
+    shapes::quad::{cuboid, Quad},
+    texture::Texture,
+};
+use glam::DVec3;
+
+fn main() -> io::Result<()> {
+    let red = Material::Lambertian {
+        albedo: Texture::Solid(DVec3::new(0.65, 0.05, 0.05)),
+    };
+    let white = Material::Lambertian {
+        albedo: Texture::Solid(DVec3::new(0.73, 0.73, 0.73)),
+    };
+    let green = Material::Lambertian {
+        albedo: Texture::Solid(DVec3::new(0.12, 0.45, 0.15)),
+    };
+    let light = Material::DiffuseLight {
+        emit: DVec3::new(15.0, 15.0, 15.0),
+    };
+
+    let mut world = HittableList { objects: vec![] };
+    world.objects.push(Box::new(Quad::new(
+        DVec3::new(555.0, 0.0, 0.0),
+        DVec3::new(0.0, 555.0, 0.0),
+        DVec3::new(0.0, 0.0, 555.0),
+        green,
+    )));
+    world.objects.push(Box::new(Quad::new(
+        DVec3::new(0.0, 0.0, 555.0),
+        DVec3::new(0.0, 555.0, 0.0),
+        DVec3::new(0.0, 0.0, -555.0),
+        red,
+    )));
+    // the light faces down into the box
+    let light_quad = || {
+        Quad::new(
+            DVec3::new(343.0, 554.0, 332.0),
+            DVec3::new(-130.0, 0.0, 0.0),
+            DVec3::new(0.0, 0.0, -105.0),
+            light.clone(),
+        )
+    };
+    world.objects.push(Box::new(light_quad()));
+    world.objects.push(Box::new(Quad::new(
+        DVec3::new(0.0, 0.0, 0.0),
+        DVec3::new(0.0, 0.0, 555.0),
+        DVec3::new(555.0, 0.0, 0.0),
+        white.clone(),
+    )));
+    world.objects.push(Box::new(Quad::new(
+        DVec3::new(555.0, 555.0, 555.0),
+        DVec3::new(-555.0, 0.0, 0.0),
+        DVec3::new(0.0, 0.0, -555.0),
+        white.clone(),
+    )));
+    world.objects.push(Box::new(Quad::new(
+        DVec3::new(0.0, 0.0, 555.0),
+        DVec3::new(555.0, 0.0, 0.0),
+        DVec3::new(0.0, 555.0, 0.0),
+        white.clone(),
+    )));
+
+    world.objects.push(Box::new(cuboid(
+        DVec3::new(130.0, 0.0, 65.0),
+        DVec3::new(295.0, 165.0, 230.0),
+        white.clone(),
+    )));
+    world.objects.push(Box::new(cuboid(
+        DVec3::new(265.0, 0.0, 295.0),
+        DVec3::new(430.0, 330.0, 460.0),
+        white,
+    )));
+
+    let camera = CameraBuilder::new()
+        .image_width(400)
+        .aspect_ratio(1.0)
+        .samples_per_pixel(200)
+        .max_depth(50)
+        .vertical_fov(40.)
+        .look_from(DVec3::new(278., 278., -800.))
+        .look_at(DVec3::new(278., 278., 0.))
+        .v_up(DVec3::Y)
+        .background(Background::Solid(DVec3::ZERO))
+        .build();
+
+    // sample the light directly as well
+    let mut lights = HittableList { objects: vec![] };
+    lights.objects.push(Box::new(light_quad()));
+
+    let world = BvhNode::new(world);
+    let _ = camera.render(&world, &lights, "output/cornell-box.png");
+    Ok(())
+}
@@ -0,0 +1,14 @@
+use std::{env, error::Error};
+
+use aurora::{bvh::BvhNode, scene::load_scene};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "scenes/cornell-box.toml".to_string());
+
+    let scene = load_scene(&path)?;
+    let camera = scene.camera.build();
+    let world = BvhNode::new(scene.world);
+    camera.render(&world, &scene.lights, &scene.render.output)
+}
@@ -1,15 +1,16 @@
 use std::io;
 
 use glam::DVec3;
-use rand::Rng;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
 
 use aurora::{
-    camera::CameraBuilder, hittable::HittableList, material::Material, shapes::sphere::Sphere,
+    bvh::BvhNode, camera::CameraBuilder, hittable::HittableList, material::Material,
+    shapes::sphere::Sphere, texture::Texture,
 };
 
 fn main() -> io::Result<()> {
     let ground_material = Material::Lambertian {
-        albedo: DVec3::new(0.5, 0.5, 0.5),
+        albedo: Texture::Solid(DVec3::new(0.5, 0.5, 0.5)),
     };
     let mut world = HittableList { objects: vec![] };
     world.objects.push(Box::new(Sphere {
@@ -17,7 +18,8 @@ fn main() -> io::Result<()> {
         radius: 1000.0,
         material: ground_material,
     }));
-    let mut rng = rand::thread_rng();
+    // fixed seed so the scene is laid out the same way on every run
+    let mut rng = SmallRng::seed_from_u64(0);
 
     for a in -11..11 {
         for b in -11..11 {
@@ -35,7 +37,9 @@ fn main() -> io::Result<()> {
 
                 if choose_mat < 0.8 {
                     let albedo = random_color_1 * random_color_2;
-                    sphere_mat = Material::Lambertian { albedo };
+                    sphere_mat = Material::Lambertian {
+                        albedo: Texture::Solid(albedo),
+                    };
                     world.objects.push(Box::new(Sphere {
                         center,
                         radius: 0.2,
@@ -48,7 +52,10 @@ fn main() -> io::Result<()> {
                         rng.gen_range(0.5..1.0),
                     );
                     let fuzz = rng.gen_range(0.0..0.5);
-                    sphere_mat = Material::Metal { albedo, fuzz };
+                    sphere_mat = Material::Metal {
+                        albedo: Texture::Solid(albedo),
+                        fuzz,
+                    };
                     world.objects.push(Box::new(Sphere {
                         center,
                         radius: 0.2,
@@ -78,7 +85,7 @@ fn main() -> io::Result<()> {
     }));
 
     let material2 = Material::Lambertian {
-        albedo: DVec3::new(0.4, 0.2, 0.1),
+        albedo: Texture::Solid(DVec3::new(0.4, 0.2, 0.1)),
     };
     world.objects.push(Box::new(Sphere {
         center: DVec3::new(-4.0, 1.0, 0.1),
@@ -87,7 +94,7 @@ fn main() -> io::Result<()> {
     }));
 
     let material3 = Material::Metal {
-        albedo: DVec3::new(0.7, 0.6, 0.5),
+        albedo: Texture::Solid(DVec3::new(0.7, 0.6, 0.5)),
         fuzz: 0.0,
     };
     world.objects.push(Box::new(Sphere {
@@ -101,14 +108,19 @@ fn main() -> io::Result<()> {
     let samples_per_pixel = 250;
     let max_depth = 25;
 
-    let mut camera = CameraBuilder::new()
+    let camera = CameraBuilder::new()
         .image_width(image_width)
         .aspect_ratio(aspect_ratio)
         .samples_per_pixel(samples_per_pixel)
         .max_depth(max_depth)
         .build();
 
-    let _ = camera.render(&world, "output/spheres-big-scene.ppm".to_string());
+    let world = BvhNode::new(world);
+    let _ = camera.render(
+        &world,
+        &HittableList::default(),
+        "output/spheres-big-scene.png",
+    );
 
     Ok(())
 }
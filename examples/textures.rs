@@ -0,0 +1,51 @@
+use std::io;
+
+use aurora::{
+    bvh::BvhNode,
+    camera::CameraBuilder,
+    hittable::HittableList,
+    material::Material,
+    shapes::sphere::Sphere,
+    texture::{
+        noise::{NoiseKind, NoiseTexture},
+        Texture,
+    },
+};
+use glam::DVec3;
+
+fn main() -> io::Result<()> {
+    let checker = Texture::checker(
+        0.32,
+        Texture::Solid(DVec3::new(0.2, 0.3, 0.1)),
+        Texture::Solid(DVec3::new(0.9, 0.9, 0.9)),
+    );
+
+    let mut world = HittableList { objects: vec![] };
+    world.objects.push(Box::new(Sphere {
+        center: DVec3::new(0.0, -1000.0, 0.0),
+        radius: 1000.0,
+        material: Material::Lambertian { albedo: checker },
+    }));
+    world.objects.push(Box::new(Sphere {
+        center: DVec3::new(0.0, 2.0, 0.0),
+        radius: 2.0,
+        material: Material::Lambertian {
+            albedo: Texture::Noise(NoiseTexture::new(NoiseKind::Marble, 4.0, 1)),
+        },
+    }));
+
+    let camera = CameraBuilder::new()
+        .image_width(400)
+        .aspect_ratio(16.0 / 9.0)
+        .samples_per_pixel(100)
+        .max_depth(50)
+        .vertical_fov(20.)
+        .look_from(DVec3::new(13., 2., 3.))
+        .look_at(DVec3::new(0., 0., 0.))
+        .v_up(DVec3::Y)
+        .build();
+
+    let world = BvhNode::new(world);
+    let _ = camera.render(&world, &HittableList::default(), "output/textures.png");
+    Ok(())
+}
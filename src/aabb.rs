@@ -0,0 +1,147 @@
+use std::ops::Range;
+
+use glam::DVec3;
+
+use crate::ray::Ray;
+
+/// Axis-aligned bounding box, stored as its minimum and maximum corners.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: DVec3,
+    pub max: DVec3,
+}
+
+impl Aabb {
+    /// A box containing nothing. The union of `EMPTY` with any box is that box.
+    pub const EMPTY: Aabb = Aabb {
+        min: DVec3::INFINITY,
+        max: DVec3::NEG_INFINITY,
+    };
+
+    /// Creates the box spanned by two opposite corners, in any order.
+    pub fn new(a: DVec3, b: DVec3) -> Self {
+        Self {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// Grows any side thinner than `delta` so flat shapes (like an axis
+    /// aligned quad) still have a box rays can hit.
+    pub fn pad(&self, delta: f64) -> Aabb {
+        let half = 0.5 * delta;
+        let thin = self.extent().cmplt(DVec3::splat(delta));
+        let pad = DVec3::select(thin, DVec3::splat(half), DVec3::ZERO);
+        Aabb {
+            min: self.min - pad,
+            max: self.max + pad,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn centroid(&self) -> DVec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn extent(&self) -> DVec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.extent();
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// Index of the axis (0 = x, 1 = y, 2 = z) along which the box is largest.
+    pub fn longest_axis(&self) -> usize {
+        let d = self.extent();
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Slab test. Returns true if the ray passes through the box anywhere
+    /// inside of `interval`.
+    pub fn hit(&self, ray: &Ray, interval: Range<f64>) -> bool {
+        let mut t_min = interval.start;
+        let mut t_max = interval.end;
+
+        for axis in 0..3 {
+            let inv_d = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv_d;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            // written so that NaNs (0 * inf) leave the interval untouched
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
+            if t_max <= t_min {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_orders_corners() {
+        let bbox = Aabb::new(DVec3::new(1., -2., 3.), DVec3::new(-1., 2., 0.));
+        assert_eq!(bbox.min, DVec3::new(-1., -2., 0.));
+        assert_eq!(bbox.max, DVec3::new(1., 2., 3.));
+    }
+
+    #[test]
+    fn test_union_with_empty() {
+        let bbox = Aabb::new(DVec3::ZERO, DVec3::ONE);
+        assert_eq!(Aabb::EMPTY.union(&bbox), bbox);
+        assert!(Aabb::EMPTY.is_empty());
+        assert_eq!(Aabb::EMPTY.surface_area(), 0.0);
+    }
+
+    #[test]
+    fn test_surface_area_and_axis() {
+        let bbox = Aabb::new(DVec3::ZERO, DVec3::new(1., 2., 3.));
+        assert_eq!(bbox.surface_area(), 22.);
+        assert_eq!(bbox.longest_axis(), 2);
+    }
+
+    #[test]
+    fn test_hit() {
+        let bbox = Aabb::new(DVec3::splat(-1.), DVec3::splat(1.));
+        let towards = Ray::new(DVec3::new(0., 0., -5.), DVec3::Z);
+        let away = Ray::new(DVec3::new(0., 0., -5.), -DVec3::Z);
+        let parallel = Ray::new(DVec3::new(2., 0., -5.), DVec3::Z);
+        assert!(bbox.hit(&towards, 0.0..f64::INFINITY));
+        assert!(!bbox.hit(&towards, 0.0..3.0));
+        assert!(!bbox.hit(&away, 0.0..f64::INFINITY));
+        assert!(!bbox.hit(&parallel, 0.0..f64::INFINITY));
+    }
+}
@@ -0,0 +1,191 @@
+//! Everything a render has gathered so far, so it can be built up over
+//! several passes and turned into an image at any point.
+
+use std::io::{self, Read, Write};
+
+use glam::DVec3;
+
+use crate::{
+    adaptive::PixelVariance,
+    aov::{Aov, AovPixel, RenderOutput},
+    film::Film,
+    filter::Filter,
+    framebuffer::Framebuffer,
+};
+
+/// What one pixel has seen besides its filtered radiance.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct PixelState {
+    pub(crate) aov: AovPixel,
+    /// Counts the pixel's samples too.
+    pub(crate) variance: PixelVariance,
+    /// The index of the pixel's next sample.
+    pub(crate) next_sample: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Accumulator {
+    pub(crate) film: Film,
+    pub(crate) pixels: Vec<PixelState>,
+    pub(crate) aovs: Vec<Aov>,
+    /// Every pixel has had the samples below this index, unless adaptive
+    /// sampling stopped it earlier. A pass cut short by a deadline leaves
+    /// it as it was.
+    pub(crate) samples_taken: u32,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32, filter: Filter, aovs: &[Aov]) -> Self {
+        Self {
+            film: Film::new(width, height, filter),
+            pixels: vec![PixelState::default(); width as usize * height as usize],
+            aovs: aovs.to_vec(),
+            samples_taken: 0,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.film.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.film.height()
+    }
+
+    /// The index the next pass starts sampling pixels at.
+    pub fn samples_taken(&self) -> u32 {
+        self.samples_taken
+    }
+
+    /// One past the highest sample index any pixel has taken, which is
+    /// beyond `samples_taken` if a pass was cut short.
+    pub fn samples_end(&self) -> u32 {
+        self.pixels
+            .iter()
+            .map(|pixel| pixel.next_sample)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The samples pixel (`x`, `y`) has taken.
+    pub fn sample_count(&self, x: u32, y: u32) -> u32 {
+        self.pixels[(y * self.width() + x) as usize]
+            .variance
+            .count()
+    }
+
+    /// The image so far, in linear RGB.
+    pub fn beauty(&self) -> Framebuffer {
+        self.film.to_framebuffer()
+    }
+
+    /// Adds the samples of `other`, a render of the same image with
+    /// different random numbers, e.g. another seed. `samples_taken` becomes
+    /// the samples per pixel of both together.
+    ///
+    /// Panics if the two differ in size.
+    pub fn merge(&mut self, other: &Accumulator) {
+        assert_eq!(self.pixels.len(), other.pixels.len());
+        self.film.add(&other.film);
+        for (pixel, other) in self.pixels.iter_mut().zip(&other.pixels) {
+            pixel.aov.merge(&other.aov);
+            pixel.variance.merge(&other.variance);
+            pixel.next_sample = pixel.next_sample.max(other.next_sample);
+        }
+        self.samples_taken += other.samples_taken;
+    }
+
+    /// The image and AOVs so far.
+    pub fn output(&self) -> RenderOutput {
+        RenderOutput {
+            beauty: self.beauty(),
+            aovs: self
+                .aovs
+                .iter()
+                .map(|aov| {
+                    let values = self
+                        .pixels
+                        .iter()
+                        .map(|pixel| pixel.aov.value(*aov))
+                        .collect();
+                    let framebuffer = Framebuffer::from_pixels(self.width(), self.height(), values);
+                    (*aov, framebuffer)
+                })
+                .collect(),
+        }
+    }
+
+    /// Writes the film, then every pixel's counts and variance, in row
+    /// order, as little endian numbers. The AOV sums are only written when
+    /// there are AOVs.
+    pub(crate) fn write_pixels(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.film.write_to(writer)?;
+        let with_aovs = !self.aovs.is_empty();
+        for pixel in &self.pixels {
+            pixel.variance.write_to(writer)?;
+            write_u32(writer, pixel.next_sample)?;
+            if with_aovs {
+                pixel.aov.write_to(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads what `write_pixels` wrote, for an accumulator of the same size
+    /// and AOVs.
+    pub(crate) fn read_pixels(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        self.film.read_from(reader)?;
+        let with_aovs = !self.aovs.is_empty();
+        for pixel in &mut self.pixels {
+            pixel.variance = PixelVariance::read_from(reader)?;
+            pixel.next_sample = read_u32(reader)?;
+            if with_aovs {
+                pixel.aov = AovPixel::read_from(reader)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_u64(writer: &mut impl Write, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_f64(writer: &mut impl Write, value: f64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_vec(writer: &mut impl Write, value: DVec3) -> io::Result<()> {
+    value
+        .to_array()
+        .iter()
+        .try_for_each(|v| write_f64(writer, *v))
+}
+
+pub(crate) fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(reader)?))
+}
+
+pub(crate) fn read_vec(reader: &mut impl Read) -> io::Result<DVec3> {
+    Ok(DVec3::new(
+        read_f64(reader)?,
+        read_f64(reader)?,
+        read_f64(reader)?,
+    ))
+}
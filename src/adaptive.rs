@@ -0,0 +1,165 @@
+//! Adaptive sampling: pixels stop taking samples once their estimated error
+//! is small enough, leaving the time for the noisy ones.
+
+use std::io::{self, Read, Write};
+
+use glam::DVec3;
+
+use crate::{
+    accumulator::{read_f64, read_u32, write_f64, write_u32},
+    tonemap::luminance,
+};
+
+/// When a pixel has had enough samples.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    /// The error a pixel has to get below to stop early, see
+    /// `PixelVariance::error`. Around 0.01 is hard to tell from a
+    /// converged render.
+    pub threshold: f64,
+    /// Samples every pixel takes before its error is trusted.
+    pub min_samples: u32,
+    /// Samples no pixel goes beyond.
+    pub max_samples: u32,
+}
+
+/// Samples a pixel takes before adaptive sampling may stop it, unless told
+/// otherwise.
+pub const DEFAULT_MIN_SAMPLES: u32 = 16;
+
+impl AdaptiveSampling {
+    /// Whether a pixel with these samples so far is done.
+    pub fn is_converged(&self, variance: &PixelVariance) -> bool {
+        let count = variance.count();
+        count >= self.max_samples
+            || (count >= self.min_samples && variance.error() < self.threshold)
+    }
+}
+
+/// Running mean and variance of the luminance of a pixel's samples, using
+/// Welford's algorithm.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PixelVariance {
+    count: u32,
+    mean: f64,
+    squared_deviations: f64,
+}
+
+impl PixelVariance {
+    pub fn add(&mut self, radiance: DVec3) {
+        let value = luminance(radiance);
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.squared_deviations += delta * (value - self.mean);
+    }
+
+    /// Combines the statistics of two sets of samples, with Chan et al.'s
+    /// parallel update.
+    pub fn merge(&mut self, other: &PixelVariance) {
+        let count = self.count + other.count;
+        if count == 0 {
+            return;
+        }
+        let delta = other.mean - self.mean;
+        let weight = other.count as f64 / count as f64;
+        self.squared_deviations +=
+            other.squared_deviations + delta * delta * self.count as f64 * weight;
+        self.mean += delta * weight;
+        self.count = count;
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// The standard error of the mean luminance, relative to the square
+    /// root of the mean: noise is easier to see in bright pixels, but much
+    /// less than in proportion to their brightness.
+    pub fn error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let variance = self.squared_deviations / (self.count - 1) as f64;
+        let standard_error = (variance / self.count as f64).sqrt();
+        standard_error / self.mean.max(1e-4).sqrt()
+    }
+
+    /// Writes the statistics as little endian numbers, for checkpoints.
+    pub(crate) fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_u32(writer, self.count)?;
+        write_f64(writer, self.mean)?;
+        write_f64(writer, self.squared_deviations)
+    }
+
+    pub(crate) fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        Ok(Self {
+            count: read_u32(reader)?,
+            mean: read_f64(reader)?,
+            squared_deviations: read_f64(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variance() {
+        let mut variance = PixelVariance::default();
+        assert_eq!(variance.error(), f64::INFINITY);
+        for value in [1.0, 3.0, 1.0, 3.0] {
+            variance.add(DVec3::splat(value));
+        }
+        assert!((variance.mean() - 2.0).abs() < 1e-12);
+        // sample variance 4/3, standard error 1/sqrt(3)
+        let expected = (1.0 / 3.0_f64).sqrt() / 2.0_f64.sqrt();
+        assert!((variance.error() - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_merge_matches_one_pass() {
+        let values = [0.5, 2.0, 1.0, 4.0, 0.0, 3.0, 1.5];
+        let mut whole = PixelVariance::default();
+        let mut first = PixelVariance::default();
+        let mut second = PixelVariance::default();
+        for (i, value) in values.iter().enumerate() {
+            whole.add(DVec3::splat(*value));
+            if i < 3 { &mut first } else { &mut second }.add(DVec3::splat(*value));
+        }
+        first.merge(&second);
+        assert_eq!(first.count(), whole.count());
+        assert!((first.mean() - whole.mean()).abs() < 1e-12);
+        assert!((first.error() - whole.error()).abs() < 1e-12);
+
+        let mut empty = PixelVariance::default();
+        empty.merge(&PixelVariance::default());
+        assert_eq!(empty.count(), 0);
+    }
+
+    #[test]
+    fn test_convergence() {
+        let adaptive = AdaptiveSampling {
+            threshold: 0.01,
+            min_samples: 4,
+            max_samples: 8,
+        };
+        let mut flat = PixelVariance::default();
+        let mut noisy = PixelVariance::default();
+        for i in 0..4 {
+            flat.add(DVec3::ONE);
+            noisy.add(DVec3::splat((i % 2) as f64));
+        }
+        assert!(adaptive.is_converged(&flat));
+        assert!(!adaptive.is_converged(&noisy));
+        for i in 0..4 {
+            noisy.add(DVec3::splat((i % 2) as f64));
+        }
+        assert!(adaptive.is_converged(&noisy));
+    }
+}
@@ -0,0 +1,323 @@
+//! Arbitrary output variables: per-pixel data about the first surface each
+//! camera ray hits, gathered alongside the beauty pass for compositing and
+//! denoising.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use glam::DVec3;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    accumulator::{read_f64, read_u32, read_vec, write_f64, write_u32, write_vec},
+    framebuffer::Framebuffer,
+    hittable::HitRecord,
+    image_format::{
+        exr::{write_exr, ExrChannel, ExrPixelType},
+        ImageFormat,
+    },
+    integrator::debug::outward_normal,
+    ray::Ray,
+    tonemap::ToneMap,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aov {
+    /// Distance from the camera, averaged over the samples that hit
+    /// something. 0 where nothing was hit.
+    Depth,
+    /// Outward world space normal in [-1, 1], averaged over the samples.
+    Normal,
+    /// Surface colour without lighting, averaged over the samples.
+    Albedo,
+    /// Index of the object in the scene plus one, so the background is 0.
+    ObjectId,
+    /// `Material::fingerprint` cut to 32 bits, 0 for the background.
+    /// Written as a 32 bit integer, see `is_integer`.
+    MaterialId,
+    /// How many samples were taken for the pixel.
+    SampleCount,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::SampleCount,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::SampleCount => "sample_count",
+        }
+    }
+
+    /// Whether the AOV holds one value per pixel, repeated in all three
+    /// channels of its framebuffer.
+    pub fn is_scalar(self) -> bool {
+        !matches!(self, Aov::Normal | Aov::Albedo)
+    }
+
+    /// Whether the AOV holds full 32 bit integers, which float channels
+    /// would round. These are always written to integer EXR channels.
+    pub fn is_integer(self) -> bool {
+        self == Aov::MaterialId
+    }
+}
+
+/// First-hit data summed over the samples of one pixel.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct AovPixel {
+    samples: u32,
+    hits: u32,
+    depth: f64,
+    normal: DVec3,
+    albedo: DVec3,
+    object_id: Option<u32>,
+    material_id: Option<u32>,
+}
+
+impl AovPixel {
+    /// Records the first hit of one camera sample, or a miss.
+    pub(crate) fn add(&mut self, ray: &Ray, hit_record: Option<&HitRecord>) {
+        self.samples += 1;
+        let Some(hit_record) = hit_record else {
+            return;
+        };
+
+        self.hits += 1;
+        self.depth += hit_record.t * ray.direction.length();
+        self.normal += outward_normal(hit_record);
+        self.albedo += hit_record.material.albedo(hit_record);
+        // ids can't be averaged, so the first sample to hit decides
+        self.object_id.get_or_insert(hit_record.object_id + 1);
+        self.material_id
+            .get_or_insert_with(|| hit_record.material.fingerprint() as u32);
+    }
+
+    /// Adds the samples of the same pixel from another render.
+    pub(crate) fn merge(&mut self, other: &AovPixel) {
+        self.samples += other.samples;
+        self.hits += other.hits;
+        self.depth += other.depth;
+        self.normal += other.normal;
+        self.albedo += other.albedo;
+        self.object_id = self.object_id.or(other.object_id);
+        self.material_id = self.material_id.or(other.material_id);
+    }
+
+    pub(crate) fn value(&self, aov: Aov) -> DVec3 {
+        let samples = self.samples.max(1) as f64;
+        match aov {
+            Aov::Depth => DVec3::splat(self.depth / self.hits.max(1) as f64),
+            Aov::Normal => self.normal / samples,
+            Aov::Albedo => self.albedo / samples,
+            Aov::ObjectId => DVec3::splat(self.object_id.unwrap_or(0) as f64),
+            Aov::MaterialId => DVec3::splat(self.material_id.unwrap_or(0) as f64),
+            Aov::SampleCount => DVec3::splat(self.samples as f64),
+        }
+    }
+
+    /// Writes the sums as little endian numbers, for checkpoints.
+    pub(crate) fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_u32(writer, self.samples)?;
+        write_u32(writer, self.hits)?;
+        write_f64(writer, self.depth)?;
+        write_vec(writer, self.normal)?;
+        write_vec(writer, self.albedo)?;
+        write_id(writer, self.object_id)?;
+        write_id(writer, self.material_id)
+    }
+
+    pub(crate) fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        Ok(Self {
+            samples: read_u32(reader)?,
+            hits: read_u32(reader)?,
+            depth: read_f64(reader)?,
+            normal: read_vec(reader)?,
+            albedo: read_vec(reader)?,
+            object_id: read_id(reader)?,
+            material_id: read_id(reader)?,
+        })
+    }
+}
+
+fn write_id(writer: &mut impl Write, id: Option<u32>) -> io::Result<()> {
+    writer.write_all(&[id.is_some() as u8])?;
+    write_u32(writer, id.unwrap_or(0))
+}
+
+fn read_id(reader: &mut impl Read) -> io::Result<Option<u32>> {
+    let mut present = [0];
+    reader.read_exact(&mut present)?;
+    let id = read_u32(reader)?;
+    Ok((present[0] != 0).then_some(id))
+}
+
+/// The beauty image and the AOVs rendered with it.
+#[derive(Clone, Debug)]
+pub struct RenderOutput {
+    pub beauty: Framebuffer,
+    pub aovs: Vec<(Aov, Framebuffer)>,
+}
+
+impl RenderOutput {
+    pub fn aov(&self, aov: Aov) -> Option<&Framebuffer> {
+        self.aovs
+            .iter()
+            .find(|(kind, _)| *kind == aov)
+            .map(|(_, framebuffer)| framebuffer)
+    }
+
+    /// Writes the beauty image to `path` and every AOV next to it, see
+    /// `aov_path`. EXR files get the AOVs as extra layers instead.
+    /// `tone_map` only applies to a beauty image in an integer format.
+    pub fn save(&self, path: impl AsRef<Path>, tone_map: &ToneMap) -> io::Result<()> {
+        let path = path.as_ref();
+        let pixel_type = match ImageFormat::from_path(path) {
+            Some(ImageFormat::ExrHalf) => Some(ExrPixelType::Half),
+            Some(ImageFormat::ExrFloat) => Some(ExrPixelType::Float),
+            _ => None,
+        };
+        if let Some(pixel_type) = pixel_type {
+            let mut writer = BufWriter::new(File::create(path)?);
+            self.encode_exr(&mut writer, pixel_type)?;
+            return writer.flush();
+        }
+
+        self.beauty.save(path, tone_map)?;
+        for (aov, framebuffer) in &self.aovs {
+            if aov.is_integer() {
+                let mut writer = BufWriter::new(File::create(aov_path(path, *aov))?);
+                let channel = ExrChannel::luminance("", ExrPixelType::Uint, framebuffer.pixels());
+                write_exr(
+                    &mut writer,
+                    framebuffer.width(),
+                    framebuffer.height(),
+                    vec![channel],
+                )?;
+                writer.flush()?;
+            } else {
+                framebuffer.save_as(aov_path(path, *aov), ImageFormat::Pfm, tone_map)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a multi-layer EXR: the beauty image as `R`, `G` and `B` in
+    /// `pixel_type`, and every AOV as a full float (or integer) layer named
+    /// after it, e.g. `normal.R` or `depth.Y`.
+    pub fn encode_exr(&self, writer: &mut impl Write, pixel_type: ExrPixelType) -> io::Result<()> {
+        let mut channels = ExrChannel::rgb("", pixel_type, self.beauty.pixels()).to_vec();
+        for (aov, framebuffer) in &self.aovs {
+            let pixels = framebuffer.pixels();
+            if aov.is_integer() {
+                channels.push(ExrChannel::luminance(
+                    aov.name(),
+                    ExrPixelType::Uint,
+                    pixels,
+                ));
+            } else if aov.is_scalar() {
+                channels.push(ExrChannel::luminance(
+                    aov.name(),
+                    ExrPixelType::Float,
+                    pixels,
+                ));
+            } else {
+                channels.extend(ExrChannel::rgb(aov.name(), ExrPixelType::Float, pixels));
+            }
+        }
+        write_exr(writer, self.beauty.width(), self.beauty.height(), channels)
+    }
+}
+
+/// Where the AOV of a render saved to `path` goes: `name.png` becomes
+/// `name.depth.pfm`. AOVs are always floating point so depths and ids
+/// survive unclamped, except for integer AOVs, which go to an EXR file
+/// such as `name.material_id.exr`.
+pub fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = if aov.is_integer() { "exr" } else { "pfm" };
+    path.with_file_name(format!("{}.{}.{}", stem, aov.name(), extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Material, texture::Texture};
+
+    #[test]
+    fn test_aov_pixel_averages() {
+        let material = Material::Lambertian {
+            albedo: Texture::Solid(DVec3::splat(0.5)),
+        };
+        let ray = Ray::new(DVec3::new(0., 0., 4.), DVec3::new(0., 0., -2.));
+        let hit_record = HitRecord::new(DVec3::ZERO, DVec3::Z, 2., &ray, &material);
+
+        let mut pixel = AovPixel::default();
+        pixel.add(&ray, Some(&hit_record));
+        pixel.add(&ray, None);
+
+        assert_eq!(pixel.value(Aov::Depth), DVec3::splat(4.));
+        assert_eq!(pixel.value(Aov::Normal), DVec3::new(0., 0., 0.5));
+        assert_eq!(pixel.value(Aov::Albedo), DVec3::splat(0.25));
+        assert_eq!(pixel.value(Aov::ObjectId), DVec3::ONE);
+        assert_eq!(pixel.value(Aov::SampleCount), DVec3::splat(2.));
+        assert_eq!(AovPixel::default().value(Aov::MaterialId), DVec3::ZERO);
+    }
+
+    #[test]
+    fn test_exr_layers() {
+        let output = RenderOutput {
+            beauty: Framebuffer::new(2, 1),
+            aovs: vec![
+                (Aov::Depth, Framebuffer::new(2, 1)),
+                (Aov::Normal, Framebuffer::new(2, 1)),
+            ],
+        };
+        let mut bytes = vec![];
+        output.encode_exr(&mut bytes, ExrPixelType::Half).unwrap();
+
+        let contains = |name: &str| {
+            let name = format!("{}\0", name);
+            bytes
+                .windows(name.len())
+                .any(|window| window == name.as_bytes())
+        };
+        for name in ["R", "depth.Y", "normal.R", "normal.B"] {
+            assert!(contains(name), "missing channel {}", name);
+        }
+        assert!(!contains("depth.R"));
+    }
+
+    #[test]
+    fn test_aov_path() {
+        assert_eq!(
+            aov_path(Path::new("out/render.png"), Aov::Depth),
+            Path::new("out/render.depth.pfm")
+        );
+        assert_eq!(
+            aov_path(Path::new("render"), Aov::ObjectId),
+            Path::new("render.object_id.pfm")
+        );
+        assert_eq!(
+            aov_path(Path::new("render.png"), Aov::MaterialId),
+            Path::new("render.material_id.exr")
+        );
+    }
+}
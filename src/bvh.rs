@@ -0,0 +1,296 @@
+use std::ops::Range;
+
+use glam::DVec3;
+
+use crate::{
+    aabb::Aabb,
+    hittable::{closest_hit, HitRecord, Hittable, HittableList},
+    ray::Ray,
+};
+
+/// Number of buckets the centroids are sorted into along each axis when
+/// searching for the cheapest split.
+const BIN_COUNT: usize = 12;
+/// Cost of visiting an interior node, relative to intersecting one primitive.
+const TRAVERSAL_COST: f64 = 1.0;
+/// Leaves never hold more primitives than this, even if SAH says splitting
+/// is not worth it.
+const MAX_LEAF_SIZE: usize = 8;
+
+/// Bounding volume hierarchy built with the surface area heuristic (SAH).
+///
+/// Build one from a `HittableList` and use it anywhere the list was used:
+/// rays only test the primitives whose bounding boxes they pass through.
+pub struct BvhNode {
+    bbox: Aabb,
+    kind: BvhKind,
+}
+
+enum BvhKind {
+    /// Primitives along with their index in the original list.
+    Leaf(Vec<(u32, Box<dyn Hittable + Send + Sync>)>),
+    Branch {
+        left: Box<BvhNode>,
+        right: Box<BvhNode>,
+        /// The axis the children were split along. `left` holds the
+        /// primitives with the smaller centroids.
+        axis: usize,
+    },
+}
+
+/// A primitive together with the bounds needed while building.
+struct BuildPrimitive {
+    id: u32,
+    bbox: Aabb,
+    centroid: DVec3,
+    object: Box<dyn Hittable + Send + Sync>,
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    bbox: Aabb,
+    count: usize,
+}
+
+impl BvhNode {
+    pub fn new(list: HittableList) -> Self {
+        let primitives = list
+            .objects
+            .into_iter()
+            .enumerate()
+            .map(|(id, object)| {
+                let bbox = object.bounding_box();
+                BuildPrimitive {
+                    id: id as u32,
+                    bbox,
+                    centroid: bbox.centroid(),
+                    object,
+                }
+            })
+            .collect();
+
+        Self::build(primitives)
+    }
+
+    fn build(primitives: Vec<BuildPrimitive>) -> Self {
+        let bbox = primitives
+            .iter()
+            .fold(Aabb::EMPTY, |acc, p| acc.union(&p.bbox));
+        let centroid_bounds = primitives.iter().fold(Aabb::EMPTY, |acc, p| {
+            acc.union(&Aabb::new(p.centroid, p.centroid))
+        });
+
+        let leaf_cost = primitives.len() as f64;
+        let split = Self::find_split(&primitives, &bbox, &centroid_bounds);
+
+        match split {
+            Some((axis, bin, cost)) if cost < leaf_cost || primitives.len() > MAX_LEAF_SIZE => {
+                let lo = centroid_bounds.min[axis];
+                let extent = centroid_bounds.max[axis] - lo;
+                let (left, right): (Vec<_>, Vec<_>) = primitives
+                    .into_iter()
+                    .partition(|p| bin_index(p.centroid[axis], lo, extent) < bin);
+                Self::branch(bbox, axis, left, right)
+            }
+            None if primitives.len() > MAX_LEAF_SIZE => {
+                // every centroid is in the same spot, so SAH cannot separate
+                // them. Split by count to keep leaves small.
+                let axis = bbox.longest_axis();
+                let mut primitives = primitives;
+                let right = primitives.split_off(primitives.len() / 2);
+                Self::branch(bbox, axis, primitives, right)
+            }
+            _ => Self {
+                bbox,
+                kind: BvhKind::Leaf(primitives.into_iter().map(|p| (p.id, p.object)).collect()),
+            },
+        }
+    }
+
+    fn branch(
+        bbox: Aabb,
+        axis: usize,
+        left: Vec<BuildPrimitive>,
+        right: Vec<BuildPrimitive>,
+    ) -> Self {
+        Self {
+            bbox,
+            kind: BvhKind::Branch {
+                left: Box::new(Self::build(left)),
+                right: Box::new(Self::build(right)),
+                axis,
+            },
+        }
+    }
+
+    /// Bins the centroids along every axis and evaluates the SAH cost of
+    /// splitting between each pair of neighbouring bins.
+    /// Returns the best (axis, first bin of the right child, cost), or None
+    /// if there is nothing to split.
+    fn find_split(
+        primitives: &[BuildPrimitive],
+        bbox: &Aabb,
+        centroid_bounds: &Aabb,
+    ) -> Option<(usize, usize, f64)> {
+        if primitives.len() < 2 {
+            return None;
+        }
+        let parent_area = bbox.surface_area();
+        let mut best: Option<(usize, usize, f64)> = None;
+
+        for axis in 0..3 {
+            let lo = centroid_bounds.min[axis];
+            let extent = centroid_bounds.max[axis] - lo;
+            if extent <= 0.0 {
+                continue;
+            }
+
+            let mut bins = [Bin {
+                bbox: Aabb::EMPTY,
+                count: 0,
+            }; BIN_COUNT];
+            for p in primitives {
+                let index = bin_index(p.centroid[axis], lo, extent);
+                bins[index].bbox = bins[index].bbox.union(&p.bbox);
+                bins[index].count += 1;
+            }
+
+            // sweep from the right so each split can read the right side's totals
+            let mut right_area = [0.0; BIN_COUNT];
+            let mut right_count = [0; BIN_COUNT];
+            let mut acc = Bin {
+                bbox: Aabb::EMPTY,
+                count: 0,
+            };
+            for i in (1..BIN_COUNT).rev() {
+                acc.bbox = acc.bbox.union(&bins[i].bbox);
+                acc.count += bins[i].count;
+                right_area[i] = acc.bbox.surface_area();
+                right_count[i] = acc.count;
+            }
+
+            let mut left = Bin {
+                bbox: Aabb::EMPTY,
+                count: 0,
+            };
+            for i in 1..BIN_COUNT {
+                left.bbox = left.bbox.union(&bins[i - 1].bbox);
+                left.count += bins[i - 1].count;
+                if left.count == 0 || right_count[i] == 0 {
+                    continue;
+                }
+
+                let cost = TRAVERSAL_COST
+                    + (left.bbox.surface_area() * left.count as f64
+                        + right_area[i] * right_count[i] as f64)
+                        / parent_area.max(f64::MIN_POSITIVE);
+                if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, i, cost));
+                }
+            }
+        }
+
+        best
+    }
+}
+
+impl From<HittableList> for BvhNode {
+    fn from(list: HittableList) -> Self {
+        Self::new(list)
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord<'_>> {
+        if !self.bbox.hit(ray, interval.clone()) {
+            return None;
+        }
+
+        match &self.kind {
+            BvhKind::Leaf(objects) => {
+                closest_hit(objects.iter().map(|(id, object)| (*id, object)), ray, interval)
+            }
+            BvhKind::Branch { left, right, axis } => {
+                // visit the child nearer to the ray origin first so its hit
+                // can cull the other one
+                let (first, second) = if ray.direction[*axis] < 0.0 {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+
+                let first_hit = first.hit(ray, interval.clone());
+                let end = first_hit.as_ref().map_or(interval.end, |hit| hit.t);
+                second.hit(ray, interval.start..end).or(first_hit)
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+fn bin_index(value: f64, lo: f64, extent: f64) -> usize {
+    let index = ((value - lo) / extent * BIN_COUNT as f64) as usize;
+    index.min(BIN_COUNT - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::Texture;
+    use crate::{material::Material, shapes::sphere::Sphere};
+
+    fn grid_of_spheres() -> HittableList {
+        let mut world = HittableList { objects: vec![] };
+        for x in -5..5 {
+            for z in -5..5 {
+                world.objects.push(Box::new(Sphere {
+                    center: DVec3::new(x as f64, 0.0, z as f64),
+                    radius: 0.3,
+                    material: Material::Lambertian {
+                        albedo: Texture::Solid(DVec3::ONE),
+                    },
+                }));
+            }
+        }
+        world
+    }
+
+    #[test]
+    fn test_bounding_box_matches_list() {
+        let list = grid_of_spheres();
+        let expected = list.bounding_box();
+        let bvh = BvhNode::new(list);
+        assert_eq!(bvh.bounding_box(), expected);
+    }
+
+    #[test]
+    fn test_hits_match_list() {
+        let list = grid_of_spheres();
+        let bvh = BvhNode::new(grid_of_spheres());
+
+        for i in 0..200 {
+            let angle = i as f64 * 0.1;
+            let ray = Ray::new(
+                DVec3::new(angle.cos() * 8.0, 3.0, angle.sin() * 8.0),
+                DVec3::new(-angle.cos(), -0.4 + 0.003 * i as f64, -angle.sin()),
+            );
+            let expected = list
+                .hit(&ray, 0.001..f64::INFINITY)
+                .map(|hit| (hit.t, hit.object_id));
+            let actual = bvh
+                .hit(&ray, 0.001..f64::INFINITY)
+                .map(|hit| (hit.t, hit.object_id));
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn test_empty_list() {
+        let bvh = BvhNode::new(HittableList { objects: vec![] });
+        let ray = Ray::new(DVec3::ZERO, DVec3::X);
+        assert!(bvh.hit(&ray, 0.0..f64::INFINITY).is_none());
+    }
+}
@@ -1,24 +1,76 @@
-use glam::DVec3;
-use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
-use rand::{rngs::SmallRng, thread_rng, Rng, SeedableRng};
+use glam::{DVec2, DVec3};
+use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 use std::{
-    cell::RefCell,
     f64::consts::PI,
-    fs::File,
-    hash::{DefaultHasher, Hash, Hasher},
-    io::{self, BufWriter, Write},
-    ptr,
+    io,
+    ops::Range,
+    path::Path,
     sync::Arc,
-    thread,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant},
 };
 
 use crate::ray::Ray;
-use crate::{fastrand::random_f64, fastrand::random_in_range, hittable::HittableList};
+use crate::{
+    accumulator::Accumulator,
+    adaptive::{AdaptiveSampling, DEFAULT_MIN_SAMPLES},
+    aov::{Aov, RenderOutput},
+    fastrand::{hash_bytes, hash_values, random_f64},
+    filter::{check_radius, Filter, FilterKind},
+    framebuffer::Framebuffer,
+    hittable::{Hittable, HittableList},
+    integrator::{Integrator, SceneView, SimplePathIntegrator, DEFAULT_ROULETTE_DEPTH},
+    sampler::{begin_sample, end_sample, Sampler, SamplerKind},
+    tonemap::ToneMap,
+};
+
+/// Rows of pixels each parallel task renders into one film tile.
+const BAND_ROWS: u32 = 8;
+
+/// What rays that escape the scene see.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Background {
+    /// Vertical gradient from white at the horizon to light blue overhead.
+    Sky,
+    /// A constant colour. Use `DVec3::ZERO` for scenes lit only by their
+    /// emissive materials.
+    Solid(DVec3),
+}
+
+impl Background {
+    /// The colour seen along `ray`.
+    pub fn color(&self, ray: &Ray) -> DVec3 {
+        match self {
+            Background::Sky => {
+                let unit_direction = ray.direction.normalize();
+                let a = 0.5 * (unit_direction.y + 1.0);
+                let white = DVec3::new(1.0, 1.0, 1.0);
+                let blue = DVec3::new(0.5, 0.7, 1.0);
+                lerp(a, white, blue)
+            }
+            Background::Solid(color) => *color,
+        }
+    }
+}
 
-const MAX_VAL: u8 = 255;
+/// How `Camera::render_progressive` splits up a render.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProgressiveOptions {
+    /// Samples per pixel each pass adds.
+    pub pass_samples: u32,
+    /// The least time between two previews. `None` previews every pass.
+    pub preview_interval: Option<Duration>,
+    /// Renders until this much time has passed instead of stopping at the
+    /// camera's samples per pixel, cutting the last pass short. Every pixel
+    /// is normalised by the samples it got, and the first pass always
+    /// completes.
+    pub time_budget: Option<Duration>,
+}
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct CameraBuilder {
     /// The ratio of width over height of the image.
     pub aspect_ratio: Option<f64>,
@@ -26,8 +78,24 @@ pub struct CameraBuilder {
     pub image_width: Option<u32>,
     /// Count of random samples per pixel
     pub samples_per_pixel: Option<u32>,
+    /// Turns on adaptive sampling: pixels stop taking samples once their
+    /// estimated error drops below this, see `AdaptiveSampling`.
+    pub adaptive_threshold: Option<f64>,
+    /// Samples every pixel takes before adaptive sampling may stop it.
+    pub min_samples: Option<u32>,
+    /// The most samples a pixel takes with adaptive sampling. Defaults to
+    /// `samples_per_pixel`.
+    pub max_samples: Option<u32>,
     /// The limit to hit if the number of ray bounces exceeds this amount.
     pub max_depth: Option<u32>,
+    /// Bounces after which Russian roulette may end dim paths early.
+    /// Set it to `max_depth` or more to always trace full paths.
+    pub roulette_depth: Option<u32>,
+    /// How samples are weighted into the pixels around them.
+    pub filter: Option<FilterKind>,
+    /// Filter radius in pixels. Defaults to the usual one for the filter.
+    #[serde(deserialize_with = "deserialize_filter_radius")]
+    pub filter_radius: Option<f64>,
     /// Angle from origin (camera location) to viewport top and bottom (usually 90 deg).
     pub vertical_fov: Option<f64>,
     /// Where the camera is positioned (usually at the origin).
@@ -46,6 +114,37 @@ pub struct CameraBuilder {
     /// The distance from the camera to the plane where objects are
     /// perfectly in focus.
     pub focus_dist: Option<f64>,
+    /// The colour returned by rays that don't hit anything.
+    pub background: Option<Background>,
+    /// Seeds every random choice made while rendering. Renders of the same
+    /// scene with the same seed are identical, whatever the thread count.
+    pub seed: Option<u64>,
+    /// Where the random numbers of each sample come from. Low discrepancy
+    /// samplers converge faster than the default independent one.
+    pub sampler: Option<SamplerKind>,
+    /// Extra per-pixel outputs gathered from the first hit of every sample.
+    /// `render` writes them next to the image.
+    pub aovs: Option<Vec<Aov>>,
+    /// Exposure, tone curve and dithering for 8 and 16 bit output.
+    pub tone_map: Option<ToneMap>,
+    /// How radiance is estimated along each camera ray. Defaults to a
+    /// `SimplePathIntegrator` limited to `max_depth` bounces, with
+    /// Russian roulette after `roulette_depth`.
+    #[serde(skip)]
+    pub integrator: Option<Arc<dyn Integrator>>,
+}
+
+impl Default for CameraBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn deserialize_filter_radius<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    let radius = f64::deserialize(deserializer)?;
+    check_radius(radius).map(Some).map_err(D::Error::custom)
 }
 
 impl CameraBuilder {
@@ -54,13 +153,25 @@ impl CameraBuilder {
             aspect_ratio: None,
             image_width: None,
             samples_per_pixel: None,
+            adaptive_threshold: None,
+            min_samples: None,
+            max_samples: None,
             max_depth: None,
+            roulette_depth: None,
+            filter: None,
+            filter_radius: None,
             vertical_fov: None,
             look_from: None,
             look_at: None,
             v_up: None,
             defocus_angle: None,
             focus_dist: None,
+            background: None,
+            seed: None,
+            sampler: None,
+            aovs: None,
+            tone_map: None,
+            integrator: None,
         }
     }
 
@@ -79,11 +190,41 @@ impl CameraBuilder {
         self
     }
 
+    pub fn adaptive_threshold(mut self, adaptive_threshold: f64) -> Self {
+        self.adaptive_threshold = Some(adaptive_threshold);
+        self
+    }
+
+    pub fn min_samples(mut self, min_samples: u32) -> Self {
+        self.min_samples = Some(min_samples);
+        self
+    }
+
+    pub fn max_samples(mut self, max_samples: u32) -> Self {
+        self.max_samples = Some(max_samples);
+        self
+    }
+
     pub fn max_depth(mut self, max_depth: u32) -> Self {
         self.max_depth = Some(max_depth);
         self
     }
 
+    pub fn roulette_depth(mut self, roulette_depth: u32) -> Self {
+        self.roulette_depth = Some(roulette_depth);
+        self
+    }
+
+    pub fn filter(mut self, filter: FilterKind) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn filter_radius(mut self, filter_radius: f64) -> Self {
+        self.filter_radius = Some(filter_radius);
+        self
+    }
+
     pub fn vertical_fov(mut self, vertical_fov: f64) -> Self {
         self.vertical_fov = Some(vertical_fov);
         self
@@ -114,23 +255,84 @@ impl CameraBuilder {
         self
     }
 
+    pub fn background(mut self, background: Background) -> Self {
+        self.background = Some(background);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn sampler(mut self, sampler: SamplerKind) -> Self {
+        self.sampler = Some(sampler);
+        self
+    }
+
+    pub fn aovs(mut self, aovs: impl IntoIterator<Item = Aov>) -> Self {
+        self.aovs = Some(aovs.into_iter().collect());
+        self
+    }
+
+    pub fn tone_map(mut self, tone_map: ToneMap) -> Self {
+        self.tone_map = Some(tone_map);
+        self
+    }
+
+    pub fn integrator(mut self, integrator: impl Integrator + 'static) -> Self {
+        self.integrator = Some(Arc::new(integrator));
+        self
+    }
+
     pub fn build(self) -> Camera {
         // supply defaults
         let image_width = self.image_width.unwrap_or(400);
         let aspect_ratio = self.aspect_ratio.unwrap_or(16. / 9.);
-        let samples_per_pixel = self.samples_per_pixel.unwrap_or(100);
+        let mut samples_per_pixel = self.samples_per_pixel.unwrap_or(100);
+        let adaptive = self.adaptive_threshold.map(|threshold| {
+            samples_per_pixel = self.max_samples.unwrap_or(samples_per_pixel);
+            AdaptiveSampling {
+                threshold,
+                min_samples: self
+                    .min_samples
+                    .unwrap_or(DEFAULT_MIN_SAMPLES)
+                    .min(samples_per_pixel),
+                max_samples: samples_per_pixel,
+            }
+        });
         let max_depth = self.max_depth.unwrap_or(50);
+        let roulette_depth = self.roulette_depth.unwrap_or(DEFAULT_ROULETTE_DEPTH);
+        let filter_kind = self.filter.unwrap_or_default();
+        let filter = match self.filter_radius {
+            Some(radius) => Filter::with_radius(filter_kind, radius),
+            None => Filter::new(filter_kind),
+        };
         let vertical_fov = self.vertical_fov.unwrap_or(20.);
         let look_from = self.look_from.unwrap_or(DVec3::new(0., 0., -1.));
         let look_at = self.look_at.unwrap_or(DVec3::ZERO);
         let v_up = self.v_up.unwrap_or(DVec3::Y);
         let defocus_angle = self.defocus_angle.unwrap_or(0.);
         let focus_dist = self.focus_dist.unwrap_or((look_from - look_at).length());
+        let background = self.background.unwrap_or(Background::Sky);
+        let seed = self.seed.unwrap_or(0);
+        let sampler = self
+            .sampler
+            .unwrap_or_default()
+            .build(seed, samples_per_pixel);
+        let aovs = self.aovs.unwrap_or_default();
+        let tone_map = self.tone_map.unwrap_or_default();
+        let integrator = self
+            .integrator
+            .unwrap_or_else(|| {
+                Arc::new(SimplePathIntegrator::new(max_depth).with_roulette_depth(roulette_depth))
+            });
 
         Camera::initialize(
             image_width,
             aspect_ratio,
             samples_per_pixel,
+            adaptive,
             max_depth,
             vertical_fov,
             look_from,
@@ -138,6 +340,13 @@ impl CameraBuilder {
             v_up,
             defocus_angle,
             focus_dist,
+            background,
+            seed,
+            sampler,
+            filter,
+            aovs,
+            tone_map,
+            integrator,
         )
     }
 }
@@ -145,7 +354,7 @@ impl CameraBuilder {
 pub struct Camera {
     image_width: u32,
     samples_per_pixel: u32,
-    pixel_samples_scale: f64,
+    adaptive: Option<AdaptiveSampling>,
     max_depth: u32,
     image_height: u32,
     camera_center: DVec3,
@@ -156,13 +365,22 @@ pub struct Camera {
     defocus_angle: f64,
     defocus_disk_u: DVec3,
     defocus_disk_v: DVec3,
+    background: Background,
+    seed: u64,
+    sampler: Arc<dyn Sampler>,
+    filter: Filter,
+    aovs: Vec<Aov>,
+    tone_map: ToneMap,
+    integrator: Arc<dyn Integrator>,
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     fn initialize(
         image_width: u32,
         aspect_ratio: f64,
         samples_per_pixel: u32,
+        adaptive: Option<AdaptiveSampling>,
         max_depth: u32,
         vertical_fov: f64,
         look_from: DVec3,
@@ -170,9 +388,14 @@ impl Camera {
         v_up: DVec3,
         defocus_angle: f64,
         focus_dist: f64,
+        background: Background,
+        seed: u64,
+        sampler: Arc<dyn Sampler>,
+        filter: Filter,
+        aovs: Vec<Aov>,
+        tone_map: ToneMap,
+        integrator: Arc<dyn Integrator>,
     ) -> Self {
-        let pixel_samples_scale = 1. / samples_per_pixel as f64;
-        
         let mut image_height = image_width as f64 / aspect_ratio;
         image_height = if image_height < 1. { 1.0 } else { image_height };
 
@@ -209,7 +432,7 @@ impl Camera {
 
         Self {
             samples_per_pixel,
-            pixel_samples_scale,
+            adaptive,
             max_depth,
             image_width,
             image_height: image_height as u32,
@@ -220,69 +443,375 @@ impl Camera {
             defocus_angle,
             defocus_disk_u,
             defocus_disk_v,
+            background,
+            seed,
+            sampler,
+            filter,
+            aovs,
+            tone_map,
+            integrator,
         }
     }
 
+    /// Renders the scene and writes it to `file_path`, in the format
+    /// matching its extension.
+    ///
+    /// `lights` holds the emissive objects that are sampled directly at
+    /// every diffuse bounce. They must also be part of `world`. Leave it
+    /// empty to rely on paths finding the lights by chance.
+    ///
+    /// Any AOVs the camera was built with are written next to the image,
+    /// see `aov::aov_path`.
     pub fn render(
-        &mut self,
-        world: &HittableList,
-        file_path: String,
+        &self,
+        world: &(dyn Hittable + Sync),
+        lights: &HittableList,
+        file_path: impl AsRef<Path>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut file = File::create(file_path)?;
-        let mut buf_writer = BufWriter::new(file);
-        self.write_ppm_header(&mut buf_writer)?;
-        let size: u64 = self.image_height as u64 * self.image_width as u64;
-
-        let bar = Arc::new(ProgressBar::new(size));
-
-        bar.set_style(
-            ProgressStyle::default_bar()
-                .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} ({percent}%)")
-                .expect("Failed to set progress bar style")
-                .progress_chars("██░"),
+        self.render_with_aovs(world, lights).save(file_path, &self.tone_map)?;
+
+        Ok(())
+    }
+
+    /// Renders the scene and returns its pixels in linear RGB.
+    pub fn render_to_buffer(
+        &self,
+        world: &(dyn Hittable + Sync),
+        lights: &HittableList,
+    ) -> Framebuffer {
+        self.render_passes(world, lights, &[]).beauty
+    }
+
+    /// Renders the scene along with the AOVs the camera was built with.
+    /// The beauty image is the same as the one `render_to_buffer` returns.
+    pub fn render_with_aovs(
+        &self,
+        world: &(dyn Hittable + Sync),
+        lights: &HittableList,
+    ) -> RenderOutput {
+        self.render_passes(world, lights, &self.aovs)
+    }
+
+    /// Renders the scene in passes of `options.pass_samples` samples per
+    /// pixel, handing what has been accumulated so far to `preview` as it
+    /// goes, see `ProgressiveOptions`. Without a time budget, the final
+    /// image is the same as `render_with_aovs` gives, up to rounding.
+    pub fn render_progressive(
+        &self,
+        world: &(dyn Hittable + Sync),
+        lights: &HittableList,
+        options: &ProgressiveOptions,
+        preview: impl FnMut(&Accumulator) -> io::Result<()>,
+    ) -> io::Result<RenderOutput> {
+        let accumulator = self.accumulator();
+        self.resume_progressive(world, lights, accumulator, options, preview)
+            .map(|accumulator| accumulator.output())
+    }
+
+    /// Like `render_progressive`, but carries on from the samples already
+    /// in `accumulator`, e.g. from `checkpoint::load_checkpoint`.
+    pub fn resume_progressive(
+        &self,
+        world: &(dyn Hittable + Sync),
+        lights: &HittableList,
+        mut accumulator: Accumulator,
+        options: &ProgressiveOptions,
+        mut preview: impl FnMut(&Accumulator) -> io::Result<()>,
+    ) -> io::Result<Accumulator> {
+        let pass_samples = options.pass_samples.max(1);
+        let scene = SceneView {
+            world,
+            lights,
+            background: self.background,
+        };
+
+        let start = Instant::now();
+        let deadline = options.time_budget.map(|budget| start + budget);
+        let (sample_limit, bar, pass_bar) = match options.time_budget {
+            Some(budget) => (u32::MAX, time_bar(budget), ProgressBar::hidden()),
+            None => {
+                let remaining = self
+                    .samples_per_pixel
+                    .saturating_sub(accumulator.samples_taken);
+                let passes = remaining.div_ceil(pass_samples);
+                let pixels = self.image_height as u64 * self.image_width as u64;
+                let bar = progress_bar(passes as u64 * pixels);
+                (self.samples_per_pixel, bar.clone(), bar)
+            }
+        };
+
+        let mut last_preview = start;
+        while accumulator.samples_taken < sample_limit {
+            let first = accumulator.samples_taken;
+            let end = first.saturating_add(pass_samples).min(sample_limit);
+            let pass_deadline = deadline.filter(|_| first > 0);
+            let taken = self.render_pass(
+                &scene,
+                &mut accumulator,
+                first..end,
+                &pass_bar,
+                pass_deadline,
+            );
+            if deadline.is_some() {
+                bar.set_position(start.elapsed().as_millis() as u64);
+                bar.set_message(format!("{} spp", accumulator.samples_taken));
+            }
+
+            // adaptive sampling may have stopped every pixel
+            let out_of_time = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if taken == 0 || out_of_time || end == sample_limit {
+                break;
+            }
+            let due = options
+                .preview_interval
+                .is_none_or(|interval| last_preview.elapsed() >= interval);
+            if due {
+                preview(&accumulator)?;
+                last_preview = Instant::now();
+            }
+        }
+
+        bar.finish();
+        println!("Finished processing in {:?}", bar.elapsed());
+        Ok(accumulator)
+    }
+
+    /// Renders the scene for `budget`, sampling all pixels evenly until it
+    /// runs out, instead of taking a fixed number of samples per pixel.
+    /// Every pixel gets at least one sample, however long that takes.
+    /// Adaptive sampling still stops pixels at its maximum.
+    pub fn render_for(
+        &self,
+        world: &(dyn Hittable + Sync),
+        lights: &HittableList,
+        budget: Duration,
+    ) -> RenderOutput {
+        let options = ProgressiveOptions {
+            pass_samples: 1,
+            preview_interval: None,
+            time_budget: Some(budget),
+        };
+        self.render_progressive(world, lights, &options, |_| Ok(()))
+            .expect("an empty preview can't fail")
+    }
+
+    /// A hash of the settings that decide which samples the camera takes
+    /// and where they end up. The samples per pixel and the tone map are
+    /// left out, so a checkpointed render can be taken further or shown
+    /// differently.
+    pub fn fingerprint(&self) -> u64 {
+        // samplers spell out all their parameters, seed included
+        let settings = format!("{:?}", &self.sampler);
+        hash_values(self.view_fingerprint(), &[hash_bytes(settings.as_bytes())])
+    }
+
+    /// Like `fingerprint`, but leaving out the sampler and seed as well:
+    /// renders with the same view fingerprint can be merged, see
+    /// `Accumulator::merge`.
+    pub fn view_fingerprint(&self) -> u64 {
+        let adaptive = self
+            .adaptive
+            .map(|adaptive| (adaptive.threshold, adaptive.min_samples));
+        let geometry = (
+            self.camera_center,
+            self.pixel_00_loc,
+            self.pixel_delta_u,
+            self.pixel_delta_v,
+            self.defocus_disk_u,
+            self.defocus_disk_v,
+        );
+        // integrators spell out all their parameters
+        let settings = format!(
+            "{:?}",
+            (
+                self.image_width,
+                self.image_height,
+                adaptive,
+                geometry,
+                self.background,
+                self.filter,
+                &self.aovs,
+                &self.integrator,
+            )
+        );
+        hash_bytes(settings.as_bytes())
+    }
+
+    /// An empty accumulator for the camera's image and AOVs.
+    pub fn accumulator(&self) -> Accumulator {
+        Accumulator::new(self.image_width, self.image_height, self.filter, &self.aovs)
+    }
+
+    /// Adds samples `samples` of every pixel to `accumulator`. Rendering
+    /// consecutive ranges one after another gives the same image as
+    /// rendering them at once, up to rounding.
+    pub fn render_samples(
+        &self,
+        world: &(dyn Hittable + Sync),
+        lights: &HittableList,
+        accumulator: &mut Accumulator,
+        samples: Range<u32>,
+    ) {
+        let scene = SceneView {
+            world,
+            lights,
+            background: self.background,
+        };
+        self.render_pass(&scene, accumulator, samples, &ProgressBar::hidden(), None);
+    }
+
+    fn render_passes(
+        &self,
+        world: &(dyn Hittable + Sync),
+        lights: &HittableList,
+        aovs: &[Aov],
+    ) -> RenderOutput {
+        let bar = progress_bar(self.image_height as u64 * self.image_width as u64);
+        let scene = SceneView {
+            world,
+            lights,
+            background: self.background,
+        };
+
+        let mut accumulator =
+            Accumulator::new(self.image_width, self.image_height, self.filter, aovs);
+        self.render_pass(
+            &scene,
+            &mut accumulator,
+            0..self.samples_per_pixel,
+            &bar,
+            None,
         );
-        bar.inc(0);
 
-        // render each pixel
-        let pixels: Vec<DVec3> = (0..(self.image_height as u32))
-            .into_par_iter()
+        println!("Finished processing in {:?}", bar.elapsed());
+        accumulator.output()
+    }
+
+    fn render_pass(
+        &self,
+        scene: &SceneView,
+        accumulator: &mut Accumulator,
+        samples: Range<u32>,
+        bar: &ProgressBar,
+        deadline: Option<Instant>,
+    ) -> u64 {
+        let Accumulator {
+            film,
+            pixels,
+            aovs,
+            samples_taken,
+        } = accumulator;
+        let band_size = (BAND_ROWS * self.image_width) as usize;
+
+        // bands of rows are rendered in parallel, each into its own film
+        // tile, and merged in order below
+        let bands: Vec<_> = pixels
+            .par_chunks_mut(band_size.max(1))
             .enumerate()
-            .map(|(i, y)| {
-                let row: Vec<DVec3> = (0..(self.image_width as u32))
-                    .map(|x| {
-                        let pixel_color: DVec3 = (0..self.samples_per_pixel)
-                            .map(|_| {
-                                let ray = self.get_ray(x, y);
-                                self.color(&ray, self.max_depth, &world)
-                            })
-                            .sum();
-
-                        self.pixel_samples_scale * pixel_color
-                    })
-                    .collect();
-                if i % 100 == 0 {
-                    // Batch updates to reduce overhead
-                    Arc::clone(&bar).inc(100 * self.image_width as u64);
+            .map(|(band, band_pixels)| {
+                let first_row = band as u32 * BAND_ROWS;
+                let rows = first_row..(first_row + BAND_ROWS).min(self.image_height);
+                let mut tile = film.tile(rows.clone());
+                let mut taken = 0;
+                for (index, pixel) in band_pixels.iter_mut().enumerate() {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return (tile, taken, false);
+                    }
+                    let x = index as u32 % self.image_width;
+                    let y = first_row + index as u32 / self.image_width;
+                    let pixel_index = y as u64 * self.image_width as u64 + x as u64;
+                    // pixels left behind by a pass cut short catch up here
+                    let first = pixel.next_sample.max(samples.start);
+                    for sample in first..samples.end {
+                        if let Some(adaptive) = &self.adaptive {
+                            if adaptive.is_converged(&pixel.variance) {
+                                break;
+                            }
+                        }
+
+                        // the random numbers of every sample depend only on
+                        // the pixel and sample index, not on which thread or
+                        // pass renders it
+                        begin_sample(&self.sampler, pixel_index, sample as u64);
+                        let offset = self.sample_square();
+                        let ray = self.get_ray(x, y, offset);
+                        let color = if aovs.is_empty() {
+                            self.integrator.radiance(&ray, scene)
+                        } else {
+                            let aov = &mut pixel.aov;
+                            self.integrator
+                                .radiance_with_first_hit(&ray, scene, &mut |hit| aov.add(&ray, hit))
+                        };
+                        end_sample();
+
+                        let position = DVec2::new(x as f64, y as f64) + 0.5 + offset.truncate();
+                        tile.add_sample(position, color);
+                        pixel.variance.add(color);
+                        pixel.next_sample = sample + 1;
+                        taken += 1;
+                    }
                 }
-                row
+                // Batch updates to reduce overhead
+                bar.inc(band_pixels.len() as u64);
+                (tile, taken, true)
             })
-            .flat_map(|row| row.to_vec())
             .collect();
 
-        pixels.into_iter().for_each(|pixel| {
-            self.write_color(pixel, &mut buf_writer)
-                .expect("Failed to write pixel color.")
-        });
+        let mut taken = 0;
+        let mut complete = true;
+        for (tile, band_taken, band_complete) in &bands {
+            film.merge(tile);
+            taken += band_taken;
+            complete &= band_complete;
+        }
+        if complete {
+            *samples_taken = (*samples_taken).max(samples.end);
+        }
+        taken
+    }
 
-        buf_writer.flush()?;
+    pub fn image_width(&self) -> u32 {
+        self.image_width
+    }
 
-        println!("Finished processing in {:?}", bar.elapsed());
+    pub fn image_height(&self) -> u32 {
+        self.image_height
+    }
 
-        Ok(())
+    /// Samples per pixel, or the most any pixel takes with adaptive
+    /// sampling.
+    pub fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    pub fn aovs(&self) -> &[Aov] {
+        &self.aovs
+    }
+
+    pub fn max_depth(&self) -> u32 {
+        self.max_depth
     }
 
-    fn get_ray(&self, x: u32, y: u32) -> Ray {
-        let offset = self.sample_square();
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn tone_map(&self) -> &ToneMap {
+        &self.tone_map
+    }
+
+    /// Where the camera rays start from (the centre of the lens).
+    pub fn center(&self) -> DVec3 {
+        self.camera_center
+    }
+
+    /// The ray through the point `offset` away from the centre of pixel
+    /// (x, y), see `sample_square`.
+    fn get_ray(&self, x: u32, y: u32, offset: DVec3) -> Ray {
         let pixel_center_offset = self.pixel_00_loc +
             ((x as f64 + offset.x) * self.pixel_delta_u) +
             ((y as f64 + offset.y) * self.pixel_delta_v);
@@ -307,84 +836,341 @@ impl Camera {
         DVec3::new(rx, ry, 0.0)
     }
 
-    fn write_color(&self, pixel_color: DVec3, writer: &mut BufWriter<File>) -> io::Result<()> {
-        let r = self.linear_to_gamma(pixel_color.x);
-        let g = self.linear_to_gamma(pixel_color.y);
-        let b = self.linear_to_gamma(pixel_color.z);
+    fn defocus_disk_sample(&self) -> DVec3 {
+        let p = random_in_unit_disk();
+        self.camera_center + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v)
+    }
+}
 
-        let adj_color = DVec3::new(
-            r.clamp(0.000, 0.999),
-            g.clamp(0.000, 0.999),
-            b.clamp(0.000, 0.999),
-        ) * MAX_VAL as f64;
+pub fn degrees_to_radians(degrees: f64) -> f64 {
+    degrees * PI / 180.0
+}
 
-        writeln!(
-            writer,
-            "{} {} {}",
-            adj_color.x as u8, adj_color.y as u8, adj_color.z as u8
-        )?;
-        // writer.write(&[adj_color.x as u8, adj_color.y as u8, adj_color.z as u8])?;
+/// A uniformly distributed point in the unit disk, from Shirley and Chiu's
+/// concentric mapping of two random numbers.
+pub fn random_in_unit_disk() -> DVec3 {
+    let a = 2.0 * random_f64() - 1.0;
+    let b = 2.0 * random_f64() - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return DVec3::ZERO;
+    }
+    let (r, phi) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    DVec3::new(r * phi.cos(), r * phi.sin(), 0.0)
+}
 
-        Ok(())
+fn lerp(a: f64, start: DVec3, end: DVec3) -> DVec3 {
+    (1.0 - a) * start + a * end
+}
+
+/// A bar that fills up over `budget`, showing the samples per pixel taken.
+fn time_bar(budget: Duration) -> ProgressBar {
+    let bar = ProgressBar::new(budget.as_millis() as u64);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] {bar:40.cyan/blue} {msg}")
+            .expect("Failed to set progress bar style")
+            .progress_chars("██░"),
+    );
+    bar.inc(0);
+    bar
+}
+
+pub(crate) fn progress_bar(length: u64) -> ProgressBar {
+    let bar = ProgressBar::new(length);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} ({percent}%)")
+            .expect("Failed to set progress bar style")
+            .progress_chars("██░"),
+    );
+    bar.inc(0);
+    bar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        material::Material,
+        shapes::{quad::Quad, sphere::Sphere},
+        texture::Texture,
+    };
+
+    fn scene() -> HittableList {
+        let mut world = HittableList { objects: vec![] };
+        world.objects.push(Box::new(Sphere {
+            center: DVec3::new(0.0, -100.5, -1.0),
+            radius: 100.0,
+            material: Material::Lambertian {
+                albedo: Texture::Solid(DVec3::splat(0.5)),
+            },
+        }));
+        world.objects.push(Box::new(Sphere {
+            center: DVec3::new(0.0, 0.0, -1.0),
+            radius: 0.5,
+            material: Material::Dielectric {
+                refractive_index: 1.5,
+            },
+        }));
+        world
     }
 
-    fn write_ppm_header(&mut self, writer: &mut BufWriter<File>) -> io::Result<()> {
-        writeln!(writer, "P3")?;
-        writeln!(writer, "{} {}", self.image_width, self.image_height)?;
-        writeln!(writer, "{}", MAX_VAL)?;
+    fn camera(seed: u64) -> Camera {
+        CameraBuilder::new()
+            .image_width(24)
+            .samples_per_pixel(4)
+            .max_depth(8)
+            .vertical_fov(90.)
+            .look_from(DVec3::ZERO)
+            .look_at(DVec3::new(0., 0., -1.))
+            .defocus_angle(2.0)
+            .seed(seed)
+            .build()
+    }
 
-        Ok(())
+    fn render_with_threads(camera: &Camera, world: &HittableList, threads: usize) -> Framebuffer {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(|| camera.render_to_buffer(world, &HittableList::default()))
     }
 
-    fn color(&self, ray: &Ray, depth: u32, world: &HittableList) -> DVec3 {
-        if depth == 0 {
-            return DVec3::ZERO;
-        }
+    #[test]
+    fn test_render_is_reproducible_across_thread_counts() {
+        let world = scene();
+        let camera = camera(1234);
+        let single = render_with_threads(&camera, &world, 1);
+        let many = render_with_threads(&camera, &world, 4);
+        assert_eq!(single, many);
+    }
 
-        if let Some(hit_record) = world.hit(ray, 0.001..f64::INFINITY) {
-            if let Some((attenuation, scattered)) = hit_record.material.scatter(&ray, &hit_record) {
-                return attenuation * self.color(&scattered, depth - 1, world);
-            }
-            return DVec3::ZERO;
-        }
+    #[test]
+    fn test_seed_changes_render() {
+        let world = scene();
+        let lights = HittableList::default();
+        let a = camera(1).render_to_buffer(&world, &lights);
+        let b = camera(2).render_to_buffer(&world, &lights);
+        assert_ne!(a, b);
+    }
 
-        // render background if we don't hit anything
-        let unit_direction = ray.direction.normalize();
-        let a = 0.5 * (unit_direction.y + 1.0);
-        let white = DVec3::new(1.0, 1.0, 1.0);
-        let blue = DVec3::new(0.5, 0.7, 1.0);
-        lerp(a, white, blue)
+    /// A grey floor lit only by a small light overhead.
+    fn small_light() -> (HittableList, HittableList) {
+        let light = || {
+            Quad::new(
+                DVec3::new(-0.25, 2.0, -0.25),
+                DVec3::new(0.5, 0.0, 0.0),
+                DVec3::new(0.0, 0.0, 0.5),
+                Material::DiffuseLight {
+                    emit: DVec3::splat(20.0),
+                },
+            )
+        };
+        let mut world = HittableList::default();
+        world.objects.push(Box::new(Quad::new(
+            DVec3::new(-5.0, 0.0, -5.0),
+            DVec3::new(0.0, 0.0, 10.0),
+            DVec3::new(10.0, 0.0, 0.0),
+            Material::Lambertian {
+                albedo: Texture::Solid(DVec3::splat(0.5)),
+            },
+        )));
+        world.objects.push(Box::new(light()));
+
+        let mut lights = HittableList::default();
+        lights.objects.push(Box::new(light()));
+        (world, lights)
     }
 
-    fn defocus_disk_sample(&self) -> DVec3 {
-        let p = random_in_unit_disk();
-        self.camera_center + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v)
+    #[test]
+    fn test_light_sampling_converges_faster() {
+        let (world, lights) = small_light();
+        let camera = CameraBuilder::new()
+            .image_width(8)
+            .aspect_ratio(1.0)
+            .samples_per_pixel(256)
+            .max_depth(2)
+            .vertical_fov(30.)
+            .look_from(DVec3::new(0., 1., 3.))
+            .look_at(DVec3::ZERO)
+            .background(Background::Solid(DVec3::ZERO))
+            .build();
+
+        let no_lights = HittableList::default();
+        let reference = camera.render_to_buffer(&world, &no_lights);
+        let sampled = camera.render_to_buffer(&world, &lights);
+
+        // both estimate the same image...
+        let (reference_mean, sampled_mean) = (reference.mean().x, sampled.mean().x);
+        assert!(sampled_mean > 0.0);
+        assert!((reference_mean - sampled_mean).abs() < 0.15 * sampled_mean);
+
+        // ...but with light sampling neighbouring floor pixels barely differ
+        let noise = |framebuffer: &Framebuffer| {
+            let row = framebuffer.rows().last().unwrap();
+            row.windows(2).map(|pair| (pair[0] - pair[1]).x.abs()).sum::<f64>()
+        };
+        assert!(noise(&sampled) < noise(&reference));
     }
 
-    /// Approximates gamma space by using 2.0 as it's easier than
-    /// raising to a power of 1/2.2
-    fn linear_to_gamma(&self, linear_component: f64) -> f64 {
-        if linear_component > 0.0 {
-            return linear_component.sqrt();
+    /// Shades every ray with its direction, ignoring the scene.
+    #[derive(Debug)]
+    struct DirectionIntegrator;
+
+    impl Integrator for DirectionIntegrator {
+        fn radiance(&self, ray: &Ray, _scene: &SceneView) -> DVec3 {
+            ray.direction.normalize().abs()
         }
+    }
 
-        0.0
+    #[test]
+    fn test_custom_integrator() {
+        let world = scene();
+        let camera = CameraBuilder::new()
+            .image_width(4)
+            .samples_per_pixel(1)
+            .look_from(DVec3::ZERO)
+            .look_at(DVec3::new(0., 0., -1.))
+            .integrator(DirectionIntegrator)
+            .build();
+        let framebuffer = camera.render_to_buffer(&world, &HittableList::default());
+        assert!(framebuffer
+            .pixels()
+            .iter()
+            .all(|pixel| pixel.z > 0.9 && pixel.max_element() <= 1.0));
     }
-}
 
-pub fn degrees_to_radians(degrees: f64) -> f64 {
-    degrees * PI / 180.0
-}
+    #[test]
+    fn test_aovs_leave_beauty_unchanged() {
+        let world = scene();
+        let lights = HittableList::default();
+        let camera = CameraBuilder::new()
+            .image_width(8)
+            .samples_per_pixel(3)
+            .look_from(DVec3::ZERO)
+            .look_at(DVec3::new(0., 0., -1.))
+            .aovs(Aov::ALL)
+            .build();
 
-pub fn random_in_unit_disk() -> DVec3 {
-    loop {
-        let p = DVec3::new(random_in_range(-1.0, 1.0), random_in_range(-1.0, 1.0), 0.0);
-        if p.length_squared() < 1.0 {
-            return p;
+        let output = camera.render_with_aovs(&world, &lights);
+        assert_eq!(output.beauty, camera.render_to_buffer(&world, &lights));
+        assert_eq!(output.aovs.len(), Aov::ALL.len());
+
+        let samples = output.aov(Aov::SampleCount).unwrap();
+        assert!(samples.pixels().iter().all(|count| *count == DVec3::splat(3.)));
+        // the glass sphere fills the middle of the view
+        let object_id = output.aov(Aov::ObjectId).unwrap();
+        assert_eq!(object_id.get(4, 2), DVec3::splat(2.));
+    }
+
+    #[test]
+    fn test_adaptive_sampling_skips_flat_pixels() {
+        let world = scene();
+        let lights = HittableList::default();
+        let camera = CameraBuilder::new()
+            .image_width(16)
+            .vertical_fov(90.)
+            .look_from(DVec3::ZERO)
+            .look_at(DVec3::new(0., 0., -1.))
+            .background(Background::Solid(DVec3::splat(0.5)))
+            .adaptive_threshold(0.01)
+            .min_samples(8)
+            .max_samples(64)
+            .aovs([Aov::SampleCount])
+            .build();
+        assert_eq!(camera.samples_per_pixel(), 64);
+
+        let output = camera.render_with_aovs(&world, &lights);
+        let samples = output.aov(Aov::SampleCount).unwrap();
+        // the sky in the corner is the same colour whatever the sample
+        assert_eq!(samples.get(0, 0), DVec3::splat(8.));
+        // while the ground under the glass sphere needs all of them
+        assert_eq!(samples.get(8, 8), DVec3::splat(64.));
+    }
+
+    #[test]
+    fn test_progressive_passes_add_up_to_one_render() {
+        let world = scene();
+        let lights = HittableList::default();
+        let camera = CameraBuilder::new()
+            .image_width(16)
+            .vertical_fov(90.)
+            .look_from(DVec3::ZERO)
+            .look_at(DVec3::new(0., 0., -1.))
+            .background(Background::Solid(DVec3::splat(0.5)))
+            .adaptive_threshold(0.01)
+            .min_samples(4)
+            .max_samples(10)
+            .aovs([Aov::SampleCount])
+            .build();
+
+        let options = ProgressiveOptions {
+            pass_samples: 3,
+            preview_interval: None,
+            time_budget: None,
+        };
+        let mut previews = vec![];
+        let progressive = camera
+            .render_progressive(&world, &lights, &options, |accumulator| {
+                previews.push(accumulator.samples_taken());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(previews, [3, 6, 9]);
+
+        let whole = camera.render_with_aovs(&world, &lights);
+        assert_eq!(progressive.aovs, whole.aovs);
+        for (a, b) in progressive
+            .beauty
+            .pixels()
+            .iter()
+            .zip(whole.beauty.pixels())
+        {
+            assert!((*a - *b).length() < 1e-9);
         }
     }
-}
 
-fn lerp(a: f64, start: DVec3, end: DVec3) -> DVec3 {
-    (1.0 - a) * start + a * end
+    #[test]
+    fn test_time_budget_samples_evenly() {
+        let world = scene();
+        let lights = HittableList::default();
+        let camera = CameraBuilder::new()
+            .image_width(16)
+            .samples_per_pixel(1)
+            .aovs([Aov::SampleCount])
+            .build();
+
+        let output = camera.render_for(&world, &lights, Duration::from_millis(50));
+        let counts: Vec<f64> = output
+            .aov(Aov::SampleCount)
+            .unwrap()
+            .pixels()
+            .iter()
+            .map(|count| count.x)
+            .collect();
+        let fewest = counts.iter().copied().fold(f64::INFINITY, f64::min);
+        let most = counts.iter().copied().fold(0.0, f64::max);
+        assert!(fewest >= 1.0);
+        assert!(most - fewest <= 1.0);
+
+        // a pass cut short is finished by the next one
+        let scene = SceneView {
+            world: &world,
+            lights: &lights,
+            background: camera.background,
+        };
+        let bar = ProgressBar::hidden();
+        let mut accumulator = camera.accumulator();
+        camera.render_pass(&scene, &mut accumulator, 0..1, &bar, None);
+        let taken = camera.render_pass(&scene, &mut accumulator, 1..2, &bar, Some(Instant::now()));
+        assert_eq!(taken, 0);
+        assert_eq!(accumulator.samples_taken(), 1);
+        camera.render_pass(&scene, &mut accumulator, 1..2, &bar, None);
+        assert_eq!(accumulator.samples_taken(), 2);
+        assert_eq!(accumulator.sample_count(3, 5), 2);
+    }
 }
@@ -0,0 +1,246 @@
+//! Saving a render in progress to disk, so one that gets killed can pick
+//! up where it left off.
+//!
+//! A checkpoint holds the film's weighted sums, every pixel's sample count,
+//! variance and next sample index, and the AOV sums. Samplers are pure
+//! functions of the pixel and sample index, so the next sample indices are
+//! all the sampler state there is. Fingerprints of the scene and the camera
+//! make sure a checkpoint is only resumed by the render it came from.
+
+use std::{
+    ffi::OsString,
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    accumulator::{read_u32, read_u64, write_u32, write_u64, Accumulator},
+    camera::Camera,
+};
+
+const MAGIC: &[u8; 8] = b"AURORACK";
+const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    NotACheckpoint,
+    UnsupportedVersion(u32),
+    /// The checkpoint was rendered from a different scene file.
+    SceneMismatch,
+    /// The checkpoint was rendered with different camera settings, see
+    /// `Camera::fingerprint`.
+    CameraMismatch,
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(error) => write!(f, "could not read checkpoint: {}", error),
+            CheckpointError::NotACheckpoint => write!(f, "not a checkpoint file"),
+            CheckpointError::UnsupportedVersion(version) => {
+                write!(f, "unsupported checkpoint version {}", version)
+            }
+            CheckpointError::SceneMismatch => {
+                write!(f, "the checkpoint was rendered from a different scene")
+            }
+            CheckpointError::CameraMismatch => {
+                write!(
+                    f,
+                    "the checkpoint was rendered with different camera settings"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CheckpointError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(error: io::Error) -> Self {
+        CheckpointError::Io(error)
+    }
+}
+
+/// Writes a checkpoint to `path`. It goes to a temporary file first, so a
+/// crash while saving leaves the previous checkpoint intact.
+pub fn save_checkpoint(
+    path: impl AsRef<Path>,
+    scene: u64,
+    camera: &Camera,
+    accumulator: &Accumulator,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut temporary = OsString::from(path.as_os_str());
+    temporary.push(".tmp");
+
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    write_checkpoint(&mut writer, scene, camera, accumulator)?;
+    writer
+        .into_inner()
+        .map_err(|error| error.into_error())?
+        .sync_all()?;
+    fs::rename(&temporary, path)
+}
+
+/// Reads the checkpoint at `path`, if it was rendered from the scene with
+/// fingerprint `scene` by `camera`.
+pub fn load_checkpoint(
+    path: impl AsRef<Path>,
+    scene: u64,
+    camera: &Camera,
+) -> Result<Accumulator, CheckpointError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    read_checkpoint(&mut reader, scene, camera)
+}
+
+pub fn write_checkpoint(
+    writer: &mut impl Write,
+    scene: u64,
+    camera: &Camera,
+    accumulator: &Accumulator,
+) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    write_u32(writer, VERSION)?;
+    write_u64(writer, scene)?;
+    write_u64(writer, camera.fingerprint())?;
+    write_u32(writer, accumulator.width())?;
+    write_u32(writer, accumulator.height())?;
+    write_u32(writer, accumulator.samples_taken)?;
+
+    accumulator.write_pixels(writer)?;
+    Ok(())
+}
+
+pub fn read_checkpoint(
+    reader: &mut impl Read,
+    scene: u64,
+    camera: &Camera,
+) -> Result<Accumulator, CheckpointError> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(CheckpointError::NotACheckpoint);
+    }
+    let version = read_u32(reader)?;
+    if version != VERSION {
+        return Err(CheckpointError::UnsupportedVersion(version));
+    }
+    if read_u64(reader)? != scene {
+        return Err(CheckpointError::SceneMismatch);
+    }
+    let fingerprint = read_u64(reader)?;
+    let width = read_u32(reader)?;
+    let height = read_u32(reader)?;
+    let mut accumulator = camera.accumulator();
+    if fingerprint != camera.fingerprint()
+        || width != accumulator.width()
+        || height != accumulator.height()
+    {
+        return Err(CheckpointError::CameraMismatch);
+    }
+    accumulator.samples_taken = read_u32(reader)?;
+
+    accumulator.read_pixels(reader)?;
+    Ok(accumulator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aov::Aov,
+        camera::{Background, CameraBuilder},
+        hittable::HittableList,
+        material::Material,
+        shapes::sphere::Sphere,
+        texture::Texture,
+    };
+    use glam::DVec3;
+
+    fn world() -> HittableList {
+        let mut world = HittableList { objects: vec![] };
+        world.objects.push(Box::new(Sphere {
+            center: DVec3::new(0.0, 0.0, -1.0),
+            radius: 0.5,
+            material: Material::Lambertian {
+                albedo: Texture::Solid(DVec3::splat(0.5)),
+            },
+        }));
+        world
+    }
+
+    fn builder() -> CameraBuilder {
+        CameraBuilder::new()
+            .image_width(12)
+            .look_from(DVec3::ZERO)
+            .look_at(DVec3::new(0., 0., -1.))
+            .background(Background::Sky)
+            .samples_per_pixel(2)
+            .aovs([Aov::Depth, Aov::SampleCount])
+    }
+
+    #[test]
+    fn test_resume_continues_where_it_stopped() {
+        let world = world();
+        let lights = HittableList::default();
+        let camera = builder().build();
+        let mut accumulator = camera.accumulator();
+        camera.render_samples(&world, &lights, &mut accumulator, 0..2);
+
+        let mut bytes = vec![];
+        write_checkpoint(&mut bytes, 7, &camera, &accumulator).unwrap();
+        // a resumed render may go on to more samples
+        let more_samples = builder().samples_per_pixel(4).build();
+        let mut resumed = read_checkpoint(&mut bytes.as_slice(), 7, &more_samples).unwrap();
+        assert_eq!(resumed.samples_taken(), 2);
+        more_samples.render_samples(&world, &lights, &mut resumed, 2..4);
+
+        camera.render_samples(&world, &lights, &mut accumulator, 2..4);
+        let (resumed, uninterrupted) = (resumed.output(), accumulator.output());
+        assert!(uninterrupted.beauty.get(6, 3).x > 0.0);
+        assert_eq!(resumed.beauty, uninterrupted.beauty);
+        assert_eq!(resumed.aovs, uninterrupted.aovs);
+    }
+
+    #[test]
+    fn test_refuses_other_renders() {
+        let camera = builder().build();
+        let mut bytes = vec![];
+        write_checkpoint(&mut bytes, 7, &camera, &camera.accumulator()).unwrap();
+
+        let read = |scene, camera: &Camera| read_checkpoint(&mut bytes.as_slice(), scene, camera);
+        assert!(read(7, &camera).is_ok());
+        assert!(matches!(
+            read(8, &camera),
+            Err(CheckpointError::SceneMismatch)
+        ));
+        for other in [
+            builder().seed(3),
+            builder().image_width(13),
+            builder().aovs([]),
+        ] {
+            assert!(matches!(
+                read(7, &other.build()),
+                Err(CheckpointError::CameraMismatch)
+            ));
+        }
+        assert!(matches!(
+            read_checkpoint(&mut &b"P6\n12 6\n255\n"[..], 7, &camera),
+            Err(CheckpointError::NotACheckpoint)
+        ));
+        assert!(matches!(
+            read_checkpoint(&mut &bytes[..40], 7, &camera),
+            Err(CheckpointError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,818 @@
+//! Rendering one image on several machines.
+//!
+//! A coordinator splits the samples per pixel into ranges and hands them
+//! out to workers over TCP. Every worker loads the same scene file with the
+//! coordinator's camera settings, renders the whole image for each range
+//! it is given, and sends back what it accumulated as a `PartialRender`.
+//! Samples depend only on their pixel and index, so the merged image is the
+//! one a single machine would have rendered, up to rounding. Adaptive
+//! sampling only sees the samples of one range at a time.
+//!
+//! Workers that can't be reached, drop the connection, report an error or
+//! take longer than the job timeout are given up on, and their ranges go
+//! to the others. Workers only run a few jobs ahead of the first one not
+//! merged yet, so the coordinator holds few results at a time.
+//!
+//! Workers don't authenticate coordinators. They only load scenes whose
+//! files are all inside their scene root, and should only be reachable by
+//! trusted machines.
+//!
+//! The coordinator opens every connection with `AURORADR` and a protocol
+//! version. After that both sides send messages: a tag byte, a little
+//! endian u64 length and that many bytes of payload.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fmt, fs,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{mpsc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    accumulator::{read_u32, read_u64, write_u32, write_u64},
+    bvh::BvhNode,
+    camera::{progress_bar, Camera, CameraBuilder},
+    partial::{PartialRender, PartialRenderError, SampleRange},
+    scene::{SceneDescription, SceneError},
+};
+
+const MAGIC: &[u8; 8] = b"AURORADR";
+const VERSION: u32 = 1;
+
+/// How long the coordinator tries to reach a worker.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A scene as the coordinator sends it to the workers.
+#[derive(Clone, Debug)]
+pub struct DistributedScene {
+    /// The text of the scene file.
+    pub source: String,
+    /// What relative paths in the scene are resolved against. Meshes and
+    /// images have to be found there on every worker.
+    pub base_dir: PathBuf,
+    /// The camera, replacing the scene's `[camera]` table. Custom
+    /// integrators can't be sent, so workers use the default one.
+    pub camera: CameraBuilder,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DistributedOptions {
+    /// Samples per pixel in each job. Smaller jobs spread the work more
+    /// evenly and lose less to a failing worker, but cost a transfer of
+    /// the whole image each.
+    pub job_samples: u32,
+    /// How long a worker may take over a job before it is given up on.
+    /// `None` waits as long as it takes.
+    pub job_timeout: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub enum DistributedError {
+    Scene(SceneError),
+    Camera(toml::ser::Error),
+    /// The workers' renders didn't add up, though each looked right.
+    Merge(PartialRenderError),
+    /// Every worker failed before the render was done. Holds why each one
+    /// did.
+    AllWorkersFailed(Vec<String>),
+}
+
+impl fmt::Display for DistributedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DistributedError::Scene(error) => write!(f, "{}", error),
+            DistributedError::Camera(error) => {
+                write!(f, "could not send the camera settings: {}", error)
+            }
+            DistributedError::Merge(error) => {
+                write!(f, "could not merge the workers' renders: {}", error)
+            }
+            DistributedError::AllWorkersFailed(failures) => {
+                write!(f, "every worker failed: {}", failures.join("; "))
+            }
+        }
+    }
+}
+
+impl Error for DistributedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DistributedError::Scene(error) => Some(error),
+            DistributedError::Camera(error) => Some(error),
+            DistributedError::Merge(error) => Some(error),
+            DistributedError::AllWorkersFailed(_) => None,
+        }
+    }
+}
+
+impl From<SceneError> for DistributedError {
+    fn from(error: SceneError) -> Self {
+        DistributedError::Scene(error)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Message {
+    Scene {
+        source: String,
+        camera: String,
+        base_dir: String,
+    },
+    Job(Range<u32>),
+    /// A serialised `PartialRender`.
+    Partial(Vec<u8>),
+    Failed(String),
+    Done,
+}
+
+impl Message {
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut payload = vec![];
+        let tag = match self {
+            Message::Scene {
+                source,
+                camera,
+                base_dir,
+            } => {
+                for text in [source, camera, base_dir] {
+                    write_u64(&mut payload, text.len() as u64)?;
+                    payload.extend_from_slice(text.as_bytes());
+                }
+                1
+            }
+            Message::Job(samples) => {
+                write_u32(&mut payload, samples.start)?;
+                write_u32(&mut payload, samples.end)?;
+                2
+            }
+            Message::Partial(bytes) => {
+                payload.extend_from_slice(bytes);
+                3
+            }
+            Message::Failed(reason) => {
+                payload.extend_from_slice(reason.as_bytes());
+                4
+            }
+            Message::Done => 5,
+        };
+        writer.write_all(&[tag])?;
+        write_u64(writer, payload.len() as u64)?;
+        writer.write_all(&payload)?;
+        writer.flush()
+    }
+
+    fn read(reader: &mut impl Read) -> io::Result<Message> {
+        let mut tag = [0];
+        reader.read_exact(&mut tag)?;
+        let length = read_u64(reader)?;
+        let mut payload = vec![];
+        reader.take(length).read_to_end(&mut payload)?;
+        if payload.len() as u64 != length {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let mut payload = payload.as_slice();
+        let text = |bytes: Vec<u8>| {
+            String::from_utf8(bytes).map_err(|error| invalid_data(&error.to_string()))
+        };
+        match tag[0] {
+            1 => {
+                let mut texts = (0..3).map(|_| {
+                    let length = read_u64(&mut payload)? as usize;
+                    let bytes = payload
+                        .get(..length)
+                        .ok_or_else(|| invalid_data("short text"))?;
+                    payload = &payload[length..];
+                    text(bytes.to_vec())
+                });
+                let mut next = || texts.next().expect("three texts");
+                Ok(Message::Scene {
+                    source: next()?,
+                    camera: next()?,
+                    base_dir: next()?,
+                })
+            }
+            2 => Ok(Message::Job(
+                read_u32(&mut payload)?..read_u32(&mut payload)?,
+            )),
+            3 => Ok(Message::Partial(payload.to_vec())),
+            4 => Ok(Message::Failed(text(payload.to_vec())?)),
+            5 => Ok(Message::Done),
+            tag => Err(invalid_data(&format!("unknown message {}", tag))),
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Jobs waiting for a worker, shared by the coordinator's threads.
+struct Queue {
+    pending: BTreeSet<usize>,
+    /// Jobs not finished yet, pending or in the hands of a worker.
+    remaining: usize,
+    /// The first job not merged yet. Workers only start jobs less than
+    /// `window` past it, so the results waiting for it to be merged don't
+    /// pile up.
+    next_merged: usize,
+    window: usize,
+    /// Set when the render can't be finished, so workers stop.
+    stopped: bool,
+    failures: Vec<String>,
+}
+
+impl Queue {
+    fn next_job(&self) -> Option<usize> {
+        if self.stopped {
+            return None;
+        }
+        let job = *self.pending.first()?;
+        (job < self.next_merged + self.window).then_some(job)
+    }
+}
+
+/// Renders `scene` on `workers` and returns the merged result. Fails only
+/// if every worker does.
+pub fn render_distributed(
+    scene: &DistributedScene,
+    workers: &[SocketAddr],
+    options: &DistributedOptions,
+) -> Result<PartialRender, DistributedError> {
+    // built here too, so workers that load different meshes or images
+    // can be told apart
+    let fingerprint = SceneDescription::parse(&scene.source)?
+        .build(&scene.base_dir)?
+        .fingerprint;
+    let camera = scene.camera.clone().build();
+    let camera_fingerprint = camera.fingerprint();
+    let hello = Message::Scene {
+        source: scene.source.clone(),
+        camera: toml::to_string(&scene.camera).map_err(DistributedError::Camera)?,
+        base_dir: scene.base_dir.to_string_lossy().into_owned(),
+    };
+
+    let samples_per_pixel = camera.samples_per_pixel();
+    let job_samples = options.job_samples.max(1);
+    let jobs: Vec<Range<u32>> = (0..samples_per_pixel)
+        .step_by(job_samples as usize)
+        .map(|first| first..(first + job_samples).min(samples_per_pixel))
+        .collect();
+    let queue = Mutex::new(Queue {
+        pending: (0..jobs.len()).collect(),
+        remaining: jobs.len(),
+        next_merged: 0,
+        // enough for every worker to have a job and one finished ahead
+        window: 2 * workers.len(),
+        stopped: false,
+        failures: vec![],
+    });
+    let changed = Condvar::new();
+    let bar = progress_bar(jobs.len() as u64);
+
+    let (sender, receiver) = mpsc::channel();
+    let merged = thread::scope(|scope| {
+        for address in workers {
+            let sender = sender.clone();
+            let (camera, hello, jobs) = (&camera, &hello, &jobs);
+            let (queue, changed) = (&queue, &changed);
+            scope.spawn(move || {
+                let result = run_worker(
+                    *address,
+                    camera,
+                    hello,
+                    jobs,
+                    options,
+                    queue,
+                    changed,
+                    |job, partial| {
+                        if partial.scene != fingerprint {
+                            return Err("rendered a different scene".into());
+                        }
+                        let expected = SampleRange {
+                            camera: camera_fingerprint,
+                            samples: jobs[job].clone(),
+                        };
+                        if partial.samples != [expected] {
+                            return Err("rendered different samples than asked".into());
+                        }
+                        sender
+                            .send((job, partial))
+                            .map_err(|error| error.to_string())
+                    },
+                );
+                if let Err(error) = result {
+                    let failure = format!("{}: {}", address, error);
+                    eprintln!("worker {}, handing its samples to the others", failure);
+                    queue.lock().unwrap().failures.push(failure);
+                }
+            });
+        }
+        drop(sender);
+
+        // merge in job order, so the sums don't depend on which worker
+        // finished first
+        let mut merged: Option<PartialRender> = None;
+        let mut finished = BTreeMap::new();
+        let mut next_job = 0;
+        for (job, partial) in receiver {
+            bar.inc(1);
+            finished.insert(job, partial);
+            while let Some(partial) = finished.remove(&next_job) {
+                let result = match &mut merged {
+                    Some(merged) => merged.merge(&partial),
+                    None => {
+                        merged = Some(partial);
+                        Ok(())
+                    }
+                };
+                if let Err(error) = result {
+                    queue.lock().unwrap().stopped = true;
+                    changed.notify_all();
+                    return Err(DistributedError::Merge(error));
+                }
+                next_job += 1;
+            }
+            queue.lock().unwrap().next_merged = next_job;
+            changed.notify_all();
+        }
+        Ok(merged.filter(|_| next_job == jobs.len()))
+    })?;
+    bar.finish();
+    println!("Finished processing in {:?}", bar.elapsed());
+
+    let mut merged = merged
+        .ok_or_else(|| DistributedError::AllWorkersFailed(queue.into_inner().unwrap().failures))?;
+    merged.accumulator.samples_taken = samples_per_pixel;
+    Ok(merged)
+}
+
+/// Feeds jobs to the worker at `address` until none are left, passing its
+/// renders of `camera`'s image to `finished`. On failure its current job
+/// goes back in the queue.
+#[allow(clippy::too_many_arguments)]
+fn run_worker(
+    address: SocketAddr,
+    camera: &Camera,
+    hello: &Message,
+    jobs: &[Range<u32>],
+    options: &DistributedOptions,
+    queue: &Mutex<Queue>,
+    changed: &Condvar,
+    mut finished: impl FnMut(usize, PartialRender) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut stream =
+        TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).map_err(|error| error.to_string())?;
+    let send = |stream: &mut TcpStream, message: &Message| {
+        message.write(stream).map_err(|error| error.to_string())
+    };
+    stream
+        .write_all(MAGIC)
+        .and_then(|_| write_u32(&mut stream, VERSION))
+        .map_err(|error| error.to_string())?;
+    send(&mut stream, hello)?;
+
+    loop {
+        let job = {
+            let mut queue = queue.lock().unwrap();
+            // jobs in the hands of other workers come back if they fail,
+            // and the window moves on as results are merged
+            while queue.next_job().is_none() && queue.remaining > 0 && !queue.stopped {
+                queue = changed.wait(queue).unwrap();
+            }
+            match queue.next_job() {
+                Some(job) => {
+                    queue.pending.remove(&job);
+                    job
+                }
+                None => return send(&mut stream, &Message::Done),
+            }
+        };
+
+        let deadline = options.job_timeout.map(|timeout| Instant::now() + timeout);
+        let result = send(&mut stream, &Message::Job(jobs[job].clone()))
+            .and_then(|_| {
+                let mut reply = Deadline::new(&stream, deadline);
+                match Message::read(&mut reply) {
+                    Ok(Message::Partial(bytes)) => {
+                        PartialRender::read_for(&mut bytes.as_slice(), camera)
+                            .map_err(|error| error.to_string())
+                    }
+                    Ok(Message::Failed(reason)) => Err(reason),
+                    Ok(_) => Err("unexpected message".to_string()),
+                    Err(error) => Err(error.to_string()),
+                }
+            })
+            .and_then(|partial| finished(job, partial));
+
+        let mut queue = queue.lock().unwrap();
+        match result {
+            Ok(()) => queue.remaining -= 1,
+            Err(_) => {
+                queue.pending.insert(job);
+            }
+        }
+        changed.notify_all();
+        result?;
+    }
+}
+
+/// Reads from a stream until a deadline, however the bytes trickle in.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Option<Instant>,
+}
+
+impl<'a> Deadline<'a> {
+    fn new(stream: &'a TcpStream, deadline: Option<Instant>) -> Self {
+        Self { stream, deadline }
+    }
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(left) if !left.is_zero() => Some(left),
+                _ => return Err(timed_out()),
+            },
+            None => None,
+        };
+        self.stream.set_read_timeout(timeout)?;
+        // sockets report running out of time as either, depending on the
+        // platform
+        self.stream.read(buf).map_err(|error| match error.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => timed_out(),
+            _ => error,
+        })
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "job timed out")
+}
+
+/// Serves coordinators connecting to `listener`, each on its own thread.
+/// Only scenes whose files are all inside `root` are rendered.
+pub fn serve(listener: TcpListener, root: PathBuf) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let root = root.clone();
+        thread::spawn(move || {
+            if let Err(error) = serve_connection(stream, &root) {
+                eprintln!("coordinator connection failed: {}", error);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Renders the jobs of one coordinator until it is done, if the scene's
+/// base directory, meshes and images are inside `root`.
+pub fn serve_connection(mut stream: TcpStream, root: &Path) -> Result<(), Box<dyn Error>> {
+    let mut magic = [0; 8];
+    stream.read_exact(&mut magic)?;
+    let version = read_u32(&mut stream)?;
+    if &magic != MAGIC || version != VERSION {
+        return Err("not an aurora coordinator, or a different version".into());
+    }
+    let Message::Scene {
+        source,
+        camera,
+        base_dir,
+    } = Message::read(&mut stream)?
+    else {
+        return Err("expected a scene".into());
+    };
+
+    let load = || -> Result<_, Box<dyn Error>> {
+        let mut description = SceneDescription::parse(&source)?;
+        description.camera = toml::from_str(&camera)?;
+        check_paths(&description, Path::new(&base_dir), root)?;
+        Ok(description.build(Path::new(&base_dir))?)
+    };
+    let scene = match load() {
+        Ok(scene) => scene,
+        Err(error) => {
+            Message::Failed(error.to_string()).write(&mut stream)?;
+            // hanging up with the coordinator's first job unread would reset
+            // the connection, which can drop the message before it is read
+            stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+            let _ = io::copy(&mut stream, &mut io::sink());
+            return Err(error);
+        }
+    };
+    let camera = scene.camera.build();
+    let world = BvhNode::new(scene.world);
+
+    loop {
+        match Message::read(&mut stream)? {
+            Message::Job(samples) => {
+                let mut accumulator = camera.accumulator();
+                camera.render_samples(&world, &scene.lights, &mut accumulator, samples.clone());
+                let partial = PartialRender::new(scene.fingerprint, &camera, samples, accumulator);
+                let mut bytes = vec![];
+                partial.write(&mut bytes)?;
+                Message::Partial(bytes).write(&mut stream)?;
+            }
+            Message::Done => return Ok(()),
+            _ => return Err("unexpected message".into()),
+        }
+    }
+}
+
+/// Fails unless `base_dir` and the files `description` loads from it are
+/// inside `root`, so coordinators can't have a worker send back renders of
+/// whatever images and meshes it can read. Material libraries named by
+/// the meshes aren't checked, they only hold colours.
+fn check_paths(
+    description: &SceneDescription,
+    base_dir: &Path,
+    root: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let root = fs::canonicalize(root)?;
+    let assets = description.asset_paths().into_iter();
+    let paths = [base_dir.to_path_buf()]
+        .into_iter()
+        .chain(assets.map(|path| base_dir.join(path)));
+    for path in paths {
+        // resolves `..` and symbolic links before comparing
+        let inside = fs::canonicalize(&path).is_ok_and(|path| path.starts_with(&root));
+        if !inside {
+            let message = format!("{} is missing or outside the scene root", path.display());
+            return Err(message.into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::Aov;
+    use glam::DVec3;
+
+    const SCENE: &str = r#"
+        [camera]
+        image_width = 12
+        aspect_ratio = 2.0
+        look_from = [0.0, 0.0, 0.0]
+        look_at = [0.0, 0.0, -1.0]
+        background = "sky"
+        samples_per_pixel = 6
+        aovs = ["sample_count"]
+
+        [materials.grey]
+        type = "lambertian"
+        albedo = [0.5, 0.5, 0.5]
+
+        [[shapes]]
+        type = "sphere"
+        center = [0.0, 0.0, -1.0]
+        radius = 0.5
+        material = "grey"
+    "#;
+
+    fn scene() -> DistributedScene {
+        DistributedScene {
+            source: SCENE.to_string(),
+            base_dir: PathBuf::from("."),
+            camera: SceneDescription::parse(SCENE).unwrap().camera,
+        }
+    }
+
+    fn worker() -> SocketAddr {
+        worker_in(PathBuf::from("."))
+    }
+
+    fn worker_in(root: PathBuf) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, root));
+        address
+    }
+
+    /// Accepts connections and hangs up on them.
+    fn flaky_worker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                drop(stream);
+            }
+        });
+        address
+    }
+
+    /// Renders every job as samples 0..2, whatever it was asked for.
+    fn mislabelling_worker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || -> io::Result<()> {
+            let (mut stream, _) = listener.accept()?;
+            stream.read_exact(&mut [0; 12])?;
+            Message::read(&mut stream)?;
+            let scene = SceneDescription::parse(SCENE)
+                .unwrap()
+                .build(Path::new("."))
+                .unwrap();
+            let camera = scene.camera.build();
+            while let Message::Job(_) = Message::read(&mut stream)? {
+                let mut accumulator = camera.accumulator();
+                camera.render_samples(&scene.world, &scene.lights, &mut accumulator, 0..2);
+                let partial = PartialRender::new(scene.fingerprint, &camera, 0..2, accumulator);
+                let mut bytes = vec![];
+                partial.write(&mut bytes)?;
+                Message::Partial(bytes).write(&mut stream)?;
+            }
+            Ok(())
+        });
+        address
+    }
+
+    /// Answers jobs with a byte at a time, never finishing.
+    fn trickling_worker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || -> io::Result<()> {
+            let (mut stream, _) = listener.accept()?;
+            stream.read_exact(&mut [0; 12])?;
+            Message::read(&mut stream)?;
+            Message::read(&mut stream)?;
+            stream.write_all(&[3])?;
+            write_u64(&mut stream, 1 << 20)?;
+            loop {
+                thread::sleep(Duration::from_millis(20));
+                stream.write_all(&[0])?;
+            }
+        });
+        address
+    }
+
+    fn unreachable() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[test]
+    fn test_workers_render_like_one_machine() {
+        let options = DistributedOptions {
+            job_samples: 2,
+            job_timeout: Some(Duration::from_secs(60)),
+        };
+        let workers = [worker(), flaky_worker(), worker(), unreachable()];
+        let merged = render_distributed(&scene(), &workers, &options).unwrap();
+        assert_eq!(merged.accumulator.samples_taken(), 6);
+
+        let local = SceneDescription::parse(SCENE)
+            .unwrap()
+            .build(Path::new("."))
+            .unwrap();
+        let camera = local.camera.build();
+        let world = BvhNode::new(local.world);
+        let mut accumulator = camera.accumulator();
+        camera.render_samples(&world, &local.lights, &mut accumulator, 0..6);
+
+        let (merged, local) = (merged.accumulator.output(), accumulator.output());
+        assert!(local.beauty.get(6, 3).x > 0.0);
+        for (a, b) in merged.beauty.pixels().iter().zip(local.beauty.pixels()) {
+            assert!((*a - *b).length() < 1e-9);
+        }
+        let samples = merged.aov(Aov::SampleCount).unwrap();
+        assert!(samples
+            .pixels()
+            .iter()
+            .all(|count| *count == DVec3::splat(6.0)));
+    }
+
+    #[test]
+    fn test_gives_up_on_workers_rendering_other_samples() {
+        let options = DistributedOptions {
+            job_samples: 2,
+            job_timeout: Some(Duration::from_secs(60)),
+        };
+        let workers = [mislabelling_worker(), worker()];
+        let merged = render_distributed(&scene(), &workers, &options).unwrap();
+        let samples = merged.accumulator.output();
+        let samples = samples.aov(Aov::SampleCount).unwrap();
+        assert!(samples
+            .pixels()
+            .iter()
+            .all(|count| *count == DVec3::splat(6.0)));
+
+        let Err(DistributedError::AllWorkersFailed(failures)) =
+            render_distributed(&scene(), &[mislabelling_worker()], &options)
+        else {
+            panic!("expected the worker to be given up on");
+        };
+        assert!(failures[0].contains("different samples"));
+    }
+
+    #[test]
+    fn test_job_timeout_covers_the_whole_reply() {
+        let options = DistributedOptions {
+            job_samples: 2,
+            job_timeout: Some(Duration::from_millis(300)),
+        };
+        let Err(DistributedError::AllWorkersFailed(failures)) =
+            render_distributed(&scene(), &[trickling_worker()], &options)
+        else {
+            panic!("expected the worker to time out");
+        };
+        assert!(failures[0].contains("timed out"), "{:?}", failures);
+    }
+
+    #[test]
+    fn test_fails_when_every_worker_does() {
+        let options = DistributedOptions {
+            job_samples: 2,
+            job_timeout: None,
+        };
+        let workers = [flaky_worker(), unreachable()];
+        let Err(DistributedError::AllWorkersFailed(failures)) =
+            render_distributed(&scene(), &workers, &options)
+        else {
+            panic!("expected every worker to fail");
+        };
+        assert_eq!(failures.len(), 2);
+
+        let mut broken = scene();
+        broken.source.push_str("material = \"missing\"\n");
+        assert!(matches!(
+            render_distributed(&broken, &[worker()], &options),
+            Err(DistributedError::Scene(_))
+        ));
+    }
+
+    #[test]
+    fn test_workers_only_load_files_under_their_root() {
+        let description = SceneDescription::parse(SCENE).unwrap();
+        assert!(check_paths(&description, Path::new("."), Path::new(".")).is_ok());
+        assert!(check_paths(&description, Path::new("."), Path::new("src")).is_err());
+
+        let mesh = "[[shapes]]\ntype = \"mesh\"\npath = \"../Cargo.toml\"\n";
+        let escaping = SceneDescription::parse(&format!("{}\n{}", SCENE, mesh)).unwrap();
+        assert_eq!(escaping.asset_paths(), [Path::new("../Cargo.toml")]);
+        assert!(check_paths(&escaping, Path::new("src"), Path::new("src")).is_err());
+        assert!(check_paths(&escaping, Path::new("src"), Path::new(".")).is_ok());
+
+        let options = DistributedOptions {
+            job_samples: 2,
+            job_timeout: None,
+        };
+        let Err(DistributedError::AllWorkersFailed(failures)) =
+            render_distributed(&scene(), &[worker_in(PathBuf::from("src"))], &options)
+        else {
+            panic!("expected the worker to refuse the scene");
+        };
+        assert!(failures[0].contains("outside the scene root"));
+    }
+
+    #[test]
+    fn test_queue_hands_out_jobs_within_window() {
+        let mut queue = Queue {
+            pending: (0..10).collect(),
+            remaining: 10,
+            next_merged: 0,
+            window: 2,
+            stopped: false,
+            failures: vec![],
+        };
+        assert_eq!(queue.next_job(), Some(0));
+        queue.pending.remove(&0);
+        queue.pending.remove(&1);
+        assert_eq!(queue.next_job(), None);
+        // a failed job comes back first
+        queue.pending.insert(1);
+        assert_eq!(queue.next_job(), Some(1));
+        queue.pending.remove(&1);
+        queue.next_merged = 1;
+        assert_eq!(queue.next_job(), Some(2));
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        for message in [
+            Message::Scene {
+                source: "[camera]".into(),
+                camera: "seed = 3".into(),
+                base_dir: "scenes".into(),
+            },
+            Message::Job(4..8),
+            Message::Partial(vec![1, 2, 3]),
+            Message::Failed("no such mesh".into()),
+            Message::Done,
+        ] {
+            let mut bytes = vec![];
+            message.write(&mut bytes).unwrap();
+            assert_eq!(Message::read(&mut bytes.as_slice()).unwrap(), message);
+            assert!(Message::read(&mut &bytes[..bytes.len() - 1]).is_err());
+        }
+    }
+}
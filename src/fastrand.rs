@@ -1,39 +1,34 @@
 use std::cell::RefCell;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-use std::thread;
-use std::time::{SystemTime, UNIX_EPOCH};
+
+use glam::DVec3;
+
+use crate::sampler::next_dimension;
+
+/// Seed the thread RNG starts from until `seed_thread_rng` is called.
+const DEFAULT_SEED: u64 = 0;
 
 // High-speed RNG algorithm (xoshiro256**)
-struct Xoshiro256 {
+pub struct Xoshiro256 {
     state: [u64; 4],
 }
 
 impl Xoshiro256 {
-    fn new(seed: u64) -> Self {
+    pub fn new(seed: u64) -> Self {
+        // SplitMix64 spreads the seed over the whole state, so even
+        // neighbouring seeds give unrelated streams
+        let mut sm = seed;
         let mut state = [0; 4];
-        let mut hasher = DefaultHasher::new();
-        seed.hash(&mut hasher);
-
-        // Seed all state elements differently
-        for i in 0..4 {
-            hasher.write_u64(i as u64);
-            state[i] = hasher.finish();
+        for element in state.iter_mut() {
+            sm = sm.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            *element = mix64(sm);
         }
-        let mut rng = Xoshiro256 { state };
-        // Warm-up the state
-        for _ in 0..16 {
-            rng.next();
-        }
-        rng
+
+        Xoshiro256 { state }
     }
 
     #[inline]
-    fn next(&mut self) -> u64 {
-        let result = self.state[0]
-            .wrapping_add(self.state[3])
-            .rotate_left(23)
-            .wrapping_add(self.state[0]);
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.state[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
         let t = self.state[1] << 17;
 
         self.state[2] ^= self.state[0];
@@ -49,26 +44,58 @@ impl Xoshiro256 {
 
     // Generate f64 in [0, 1) with full 53-bit precision
     #[inline]
-    fn next_f64(&mut self) -> f64 {
-        let u = self.next() >> 11;
+    pub fn next_f64(&mut self) -> f64 {
+        let u = self.next_u64() >> 11;
         (u as f64) * (1.0 / (1u64 << 53) as f64)
     }
 }
 
+/// SplitMix64 finaliser: a cheap, well distributed 64 bit hash.
+#[inline]
+pub fn mix64(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Folds `values` into a hash, starting from `tag`.
+pub(crate) fn hash_values(tag: u64, values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(mix64(tag), |hash, value| mix64(hash ^ value))
+}
+
+pub(crate) fn hash_vec(tag: u64, v: DVec3) -> u64 {
+    hash_values(tag, &[v.x.to_bits(), v.y.to_bits(), v.z.to_bits()])
+}
+
+pub(crate) fn hash_bytes(bytes: &[u8]) -> u64 {
+    let words = bytes.chunks(8).map(|chunk| {
+        let mut word = [0; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        u64::from_le_bytes(word)
+    });
+    words.fold(mix64(bytes.len() as u64), |hash, word| mix64(hash ^ word))
+}
+
 thread_local! {
-    static THREAD_RNG: RefCell<Xoshiro256> = {
-        // Unique seed per thread combining time and thread ID
-        let thread_id = thread::current().id().as_u64().get();
-        let time_seed = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos() as u64;
-
-        RefCell::new(Xoshiro256::new(time_seed ^ thread_id))
-    };
+    static THREAD_RNG: RefCell<Xoshiro256> = RefCell::new(Xoshiro256::new(DEFAULT_SEED));
 }
 
+/// Restarts the current thread's RNG from `seed`. Everything drawn
+/// afterwards on this thread is fully determined by the seed. Renders draw
+/// from their samplers instead, so only tests need this.
+#[cfg(test)]
+pub(crate) fn seed_thread_rng(seed: u64) {
+    THREAD_RNG.with(|rng| *rng.borrow_mut() = Xoshiro256::new(seed));
+}
+
+/// A number in [0, 1): the next dimension of the camera sample being traced,
+/// see `sampler::begin_sample`, or else the next number of the thread RNG.
 pub fn random_f64() -> f64 {
+    if let Some(value) = next_dimension() {
+        return value;
+    }
     THREAD_RNG.with(|rng| {
         let mut rng = rng.borrow_mut();
         rng.next_f64()
@@ -76,10 +103,32 @@ pub fn random_f64() -> f64 {
 }
 
 pub fn random_in_range(min: f64, max: f64) -> f64 {
-    THREAD_RNG.with(|rng| {
-        let mut rng = rng.borrow_mut();
-        let (a, b) = if min <= max { (min, max) } else { (max, min) };
-        let val = rng.next_f64();
-        a + val * (b - a)
-    })
+    let (a, b) = if min <= max { (min, max) } else { (max, min) };
+    let val = random_f64();
+    a + val * (b - a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_stream() {
+        let mut a = Xoshiro256::new(42);
+        let mut b = Xoshiro256::new(42);
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Xoshiro256::new(1).next_u64(), Xoshiro256::new(2).next_u64());
+    }
+
+    #[test]
+    fn test_seed_thread_rng() {
+        seed_thread_rng(7);
+        let first: Vec<f64> = (0..5).map(|_| random_f64()).collect();
+        seed_thread_rng(7);
+        let second: Vec<f64> = (0..5).map(|_| random_f64()).collect();
+        assert_eq!(first, second);
+        assert!(first.iter().all(|x| (0.0..1.0).contains(x)));
+    }
 }
@@ -0,0 +1,240 @@
+//! Accumulates filtered samples into pixels.
+//!
+//! Every sample is splatted into all the pixels its filter reaches. Threads
+//! render bands of rows into their own `FilmTile`, and the tiles are merged
+//! into the `Film` one after another in row order, so the floating point
+//! sums, and with them the image, don't depend on the thread count.
+
+use std::{
+    io::{self, Read, Write},
+    ops::Range,
+};
+
+use glam::{DVec2, DVec3};
+
+use crate::{
+    accumulator::{read_f64, read_vec, write_f64, write_vec},
+    filter::Filter,
+    framebuffer::Framebuffer,
+};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct FilmPixel {
+    weighted_sum: DVec3,
+    weight: f64,
+}
+
+impl FilmPixel {
+    fn color(&self) -> DVec3 {
+        // pixels close to the border can end up with (almost) no weight
+        // from filters with negative lobes
+        if self.weight.abs() < 1e-12 {
+            return DVec3::ZERO;
+        }
+        self.weighted_sum / self.weight
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Film {
+    width: u32,
+    height: u32,
+    filter: Filter,
+    pixels: Vec<FilmPixel>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Self {
+        Self {
+            width,
+            height,
+            filter,
+            pixels: vec![FilmPixel::default(); width as usize * height as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    /// An empty tile for the samples of the pixels in `rows`, with room for
+    /// them to spill into the rows around.
+    pub fn tile(&self, rows: Range<u32>) -> FilmTile {
+        let margin = self.filter.radius.ceil() as u32;
+        let first_row = rows.start.saturating_sub(margin);
+        let end_row = rows.end.saturating_add(margin).min(self.height);
+        FilmTile {
+            width: self.width,
+            first_row,
+            end_row,
+            filter: self.filter,
+            pixels: vec![
+                FilmPixel::default();
+                self.width as usize * (end_row - first_row) as usize
+            ],
+        }
+    }
+
+    /// Adds the samples of `tile`. Merging the same tiles in the same order
+    /// always gives the same film.
+    pub fn merge(&mut self, tile: &FilmTile) {
+        let start = tile.first_row as usize * self.width as usize;
+        for (pixel, tile_pixel) in self.pixels[start..].iter_mut().zip(&tile.pixels) {
+            pixel.weighted_sum += tile_pixel.weighted_sum;
+            pixel.weight += tile_pixel.weight;
+        }
+    }
+
+    /// Adds the samples of a film of the same size.
+    pub fn add(&mut self, other: &Film) {
+        for (pixel, other) in self.pixels.iter_mut().zip(&other.pixels) {
+            pixel.weighted_sum += other.weighted_sum;
+            pixel.weight += other.weight;
+        }
+    }
+
+    /// Writes every pixel's weighted sum and weight, in row order, as
+    /// little endian numbers.
+    pub(crate) fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        for pixel in &self.pixels {
+            write_vec(writer, pixel.weighted_sum)?;
+            write_f64(writer, pixel.weight)?;
+        }
+        Ok(())
+    }
+
+    /// Reads what `write_to` wrote for a film of the same size.
+    pub(crate) fn read_from(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        for pixel in &mut self.pixels {
+            pixel.weighted_sum = read_vec(reader)?;
+            pixel.weight = read_f64(reader)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_framebuffer(&self) -> Framebuffer {
+        let pixels = self.pixels.iter().map(FilmPixel::color).collect();
+        Framebuffer::from_pixels(self.width, self.height, pixels)
+    }
+}
+
+/// Part of a `Film` that one thread splats samples into.
+#[derive(Clone, Debug)]
+pub struct FilmTile {
+    width: u32,
+    first_row: u32,
+    end_row: u32,
+    filter: Filter,
+    pixels: Vec<FilmPixel>,
+}
+
+impl FilmTile {
+    /// Splats a sample taken at `position`, in pixels from the top left
+    /// corner of the image, into every pixel of the tile the filter reaches.
+    pub fn add_sample(&mut self, position: DVec2, radiance: DVec3) {
+        let radius = self.filter.radius;
+        // pixel centres are at half integer positions
+        let min = (position - radius - 0.5).floor().max(DVec2::ZERO);
+        let max = (position + radius - 0.5).floor();
+        let (min_x, max_x) = (min.x as u32, (max.x as i64).min(self.width as i64 - 1));
+        let min_y = (min.y as u32).max(self.first_row);
+        let max_y = (max.y as i64).min(self.end_row as i64 - 1);
+
+        for y in min_y as i64..=max_y {
+            let weight_y = self.filter.evaluate_1d(position.y - (y as f64 + 0.5));
+            if weight_y == 0.0 {
+                continue;
+            }
+            let row = (y as u32 - self.first_row) as usize * self.width as usize;
+            for x in min_x as i64..=max_x {
+                let weight = weight_y * self.filter.evaluate_1d(position.x - (x as f64 + 0.5));
+                if weight != 0.0 {
+                    let pixel = &mut self.pixels[row + x as usize];
+                    pixel.weighted_sum += weight * radiance;
+                    pixel.weight += weight;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterKind;
+
+    #[test]
+    fn test_box_filter_averages_within_pixel() {
+        let mut film = Film::new(2, 1, Filter::default());
+        let mut tile = film.tile(0..1);
+        tile.add_sample(DVec2::new(0.0, 0.5), DVec3::ONE);
+        tile.add_sample(DVec2::new(0.99, 0.5), DVec3::ZERO);
+        tile.add_sample(DVec2::new(1.5, 0.0), DVec3::splat(4.0));
+        film.merge(&tile);
+
+        let framebuffer = film.to_framebuffer();
+        assert_eq!(framebuffer.get(0, 0), DVec3::splat(0.5));
+        assert_eq!(framebuffer.get(1, 0), DVec3::splat(4.0));
+    }
+
+    #[test]
+    fn test_wide_filter_spills_into_neighbours() {
+        let mut film = Film::new(5, 3, Filter::with_radius(FilterKind::Tent, 1.5));
+        let mut tile = film.tile(1..2);
+        tile.add_sample(DVec2::new(1.5, 1.5), DVec3::ONE);
+        tile.add_sample(DVec2::new(1.9, 1.5), DVec3::ZERO);
+        film.merge(&tile);
+
+        let framebuffer = film.to_framebuffer();
+        // the neighbour to the right is closer to the dark sample
+        assert!(framebuffer.get(2, 1).x < framebuffer.get(1, 1).x);
+        assert!(framebuffer.get(1, 0).x > 0.0);
+        assert_eq!(framebuffer.get(4, 1), DVec3::ZERO);
+    }
+
+    #[test]
+    fn test_tiles_merge_like_one() {
+        let filter = Filter::new(FilterKind::Mitchell);
+        let samples: Vec<(DVec2, DVec3)> = (0..40)
+            .map(|i| {
+                let position = DVec2::new((i % 8) as f64 * 0.7, (i / 8) as f64 * 0.9);
+                (position, DVec3::new(i as f64, 1.0, 0.5))
+            })
+            .collect();
+
+        let mut whole = Film::new(6, 5, filter);
+        let mut tile = whole.tile(0..5);
+        for (position, radiance) in &samples {
+            tile.add_sample(*position, *radiance);
+        }
+        whole.merge(&tile);
+
+        let mut banded = Film::new(6, 5, filter);
+        for rows in [0..2, 2..4, 4..5] {
+            let mut tile = banded.tile(rows.clone());
+            for (position, radiance) in &samples {
+                if rows.contains(&(position.y as u32)) {
+                    tile.add_sample(*position, *radiance);
+                }
+            }
+            banded.merge(&tile);
+        }
+
+        for (a, b) in whole
+            .to_framebuffer()
+            .pixels()
+            .iter()
+            .zip(banded.to_framebuffer().pixels())
+        {
+            assert!((*a - *b).length() < 1e-9);
+        }
+    }
+}
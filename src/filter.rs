@@ -0,0 +1,211 @@
+//! Reconstruction filters: how much a sample counts towards each pixel
+//! around it.
+
+use std::f64::consts::PI;
+
+use glam::DVec2;
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    /// Every sample counts fully towards the pixel it was taken in only.
+    #[default]
+    Box,
+    /// Weight falling off linearly with distance.
+    Tent,
+    /// Soft, with no ringing, at the cost of some blur.
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3: sharp with little ringing.
+    Mitchell,
+    /// Windowed sinc: the sharpest, but rings around hard edges.
+    Lanczos,
+}
+
+impl FilterKind {
+    pub const ALL: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::Lanczos,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::Mitchell => "mitchell",
+            FilterKind::Lanczos => "lanczos",
+        }
+    }
+
+    /// The radius in pixels the filter is usually used with.
+    pub fn default_radius(self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell | FilterKind::Lanczos => 2.0,
+        }
+    }
+}
+
+/// The widest filter radius in pixels. Every sample is splatted over the
+/// pixels within the radius, so wider filters only cost time.
+pub const MAX_RADIUS: f64 = 16.0;
+
+/// Accepts radii filters can work with: a zero radius catches no samples,
+/// and tent and Gaussian filters divide by it.
+pub fn check_radius(radius: f64) -> Result<f64, String> {
+    if radius > 0.0 && radius <= MAX_RADIUS {
+        Ok(radius)
+    } else {
+        Err(format!(
+            "filter radius must be above 0 and at most {}, not {}",
+            MAX_RADIUS, radius
+        ))
+    }
+}
+
+/// A separable filter, zero beyond `radius` pixels on either axis.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new(FilterKind::Box)
+    }
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Self {
+        Self {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    /// Panics unless `radius` is positive and finite, see `check_radius`.
+    pub fn with_radius(kind: FilterKind, radius: f64) -> Self {
+        assert!(
+            check_radius(radius).is_ok(),
+            "invalid filter radius {}",
+            radius
+        );
+        Self { kind, radius }
+    }
+
+    /// The weight of a sample `offset` pixels away from a pixel centre.
+    /// Mitchell and Lanczos weights can be negative.
+    pub fn evaluate(&self, offset: DVec2) -> f64 {
+        self.evaluate_1d(offset.x) * self.evaluate_1d(offset.y)
+    }
+
+    pub fn evaluate_1d(&self, x: f64) -> f64 {
+        let r = self.radius;
+        match self.kind {
+            // half open, so a sample on the border between two pixels only
+            // counts once
+            FilterKind::Box => {
+                if (-r..r).contains(&x) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            FilterKind::Tent => (1.0 - x.abs() / r).max(0.0),
+            FilterKind::Gaussian => {
+                // shifted down so it reaches zero at the radius
+                let sigma = r / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(r)).max(0.0)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / r, 1.0 / 3.0, 1.0 / 3.0),
+            FilterKind::Lanczos => {
+                if x.abs() < r {
+                    sinc(x) * sinc(x / r)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// The Mitchell-Netravali cubic on [-2, 2].
+fn mitchell(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    let value = if x <= 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * x.powi(3)
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b)
+    } else if x <= 2.0 {
+        (-b - 6.0 * c) * x.powi(3)
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c)
+    } else {
+        0.0
+    };
+    value / 6.0
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_peak_at_centre_and_vanish_at_radius() {
+        for kind in FilterKind::ALL {
+            let filter = Filter::new(kind);
+            let centre = filter.evaluate_1d(0.0);
+            assert!(centre > 0.0, "{:?}", kind);
+            assert_eq!(filter.evaluate(DVec2::ZERO), centre * centre);
+            for x in [0.1, 0.4, 0.9, 1.7] {
+                assert!(filter.evaluate_1d(x) <= centre, "{:?} at {}", kind, x);
+                assert_eq!(filter.evaluate_1d(x), filter.evaluate_1d(-x));
+            }
+            assert!(
+                filter.evaluate_1d(filter.radius).abs() < 1e-12,
+                "{:?}",
+                kind
+            );
+            assert_eq!(filter.evaluate_1d(filter.radius + 0.1), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_box_is_half_open() {
+        let filter = Filter::default();
+        assert_eq!(filter.evaluate_1d(-0.5), 1.0);
+        assert_eq!(filter.evaluate_1d(0.5), 0.0);
+    }
+
+    #[test]
+    fn test_negative_lobes() {
+        assert!(Filter::new(FilterKind::Mitchell).evaluate_1d(1.5) < 0.0);
+        assert!(Filter::new(FilterKind::Lanczos).evaluate_1d(1.5) < 0.0);
+        assert!(Filter::new(FilterKind::Gaussian).evaluate_1d(1.4) > 0.0);
+    }
+
+    #[test]
+    fn test_radius_must_be_positive() {
+        assert_eq!(check_radius(1.5), Ok(1.5));
+        assert_eq!(check_radius(MAX_RADIUS), Ok(MAX_RADIUS));
+        for radius in [0.0, -1.0, f64::NAN, f64::INFINITY, 5e9] {
+            assert!(check_radius(radius).is_err(), "{}", radius);
+        }
+    }
+}
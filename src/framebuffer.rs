@@ -0,0 +1,177 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use glam::DVec3;
+
+use crate::{
+    image_format::{write_image, write_image_as, ImageFormat},
+    tonemap::ToneMap,
+};
+
+/// A rendered image in linear RGB, stored row by row from the top left.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<DVec3>,
+}
+
+impl Framebuffer {
+    /// Creates a black image.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![DVec3::ZERO; width as usize * height as usize],
+        }
+    }
+
+    /// Wraps existing pixels. Panics if there are not exactly
+    /// `width * height` of them.
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<DVec3>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width as usize * height as usize,
+            "pixel count must match the image size"
+        );
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[DVec3] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [DVec3] {
+        &mut self.pixels
+    }
+
+    pub fn into_pixels(self) -> Vec<DVec3> {
+        self.pixels
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> DVec3 {
+        self.pixels[self.index(x, y)]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: DVec3) {
+        let index = self.index(x, y);
+        self.pixels[index] = color;
+    }
+
+    /// Iterates over the rows, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[DVec3]> {
+        self.pixels.chunks(self.width.max(1) as usize)
+    }
+
+    /// Iterates over every pixel along with its (x, y) position.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u32, DVec3)> + '_ {
+        self.pixels.iter().enumerate().map(|(i, pixel)| {
+            let i = i as u32;
+            (i % self.width, i / self.width, *pixel)
+        })
+    }
+
+    /// Applies `f` to every pixel.
+    pub fn map(&mut self, f: impl Fn(DVec3) -> DVec3) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel = f(*pixel));
+    }
+
+    /// Average colour over the whole image.
+    pub fn mean(&self) -> DVec3 {
+        if self.pixels.is_empty() {
+            return DVec3::ZERO;
+        }
+        self.pixels.iter().sum::<DVec3>() / self.pixels.len() as f64
+    }
+
+    /// Writes the image in the format matching the extension of `path`.
+    pub fn save(&self, path: impl AsRef<Path>, tone_map: &ToneMap) -> io::Result<()> {
+        write_image(path, self.width, self.height, &self.pixels, tone_map)
+    }
+
+    /// Writes the image in the given format, whatever the extension of `path`.
+    pub fn save_as(
+        &self,
+        path: impl AsRef<Path>,
+        format: ImageFormat,
+        tone_map: &ToneMap,
+    ) -> io::Result<()> {
+        write_image_as(
+            path,
+            format,
+            self.width,
+            self.height,
+            &self.pixels,
+            tone_map,
+        )
+    }
+
+    pub fn encode(
+        &self,
+        writer: &mut impl Write,
+        format: ImageFormat,
+        tone_map: &ToneMap,
+    ) -> io::Result<()> {
+        format.encode(writer, self.width, self.height, &self.pixels, tone_map)
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        assert!(
+            x < self.width && y < self.height,
+            "pixel ({}, {}) is outside of the {}x{} image",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        (y * self.width + x) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_set() {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        framebuffer.set(2, 1, DVec3::ONE);
+        assert_eq!(framebuffer.get(2, 1), DVec3::ONE);
+        assert_eq!(framebuffer.get(1, 1), DVec3::ZERO);
+        assert_eq!(framebuffer.pixels()[5], DVec3::ONE);
+    }
+
+    #[test]
+    fn test_rows_and_iter() {
+        let pixels = (0..6).map(|i| DVec3::splat(i as f64)).collect();
+        let framebuffer = Framebuffer::from_pixels(3, 2, pixels);
+
+        let rows: Vec<_> = framebuffer.rows().collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1][0], DVec3::splat(3.));
+
+        let (x, y, pixel) = framebuffer.iter().nth(4).unwrap();
+        assert_eq!((x, y, pixel), (1, 1, DVec3::splat(4.)));
+        assert_eq!(framebuffer.mean(), DVec3::splat(2.5));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_from_pixels_size_mismatch() {
+        Framebuffer::from_pixels(2, 2, vec![DVec3::ZERO; 3]);
+    }
+}
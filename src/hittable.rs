@@ -1,50 +1,130 @@
-use std::ops::Range;
+use std::{ops::Range, sync::Arc};
 
 use glam::DVec3;
 
-use crate::{material::Material, ray::Ray};
+use crate::{aabb::Aabb, fastrand::random_f64, material::Material, ray::Ray};
 
 pub trait Hittable {
-    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord<'_>>;
+
+    /// The box enclosing the whole object, used to build acceleration structures.
+    fn bounding_box(&self) -> Aabb;
+
+    /// Solid angle density with which `random(origin)` picks `direction`.
+    /// Objects that can't be sampled as lights return 0.
+    fn pdf_value(&self, _origin: DVec3, _direction: DVec3) -> f64 {
+        0.0
+    }
+
+    /// A random direction from `origin` towards a point on the object.
+    /// The direction doesn't need to be normalized.
+    fn random(&self, _origin: DVec3) -> DVec3 {
+        DVec3::X
+    }
 }
 
+#[derive(Default)]
 pub struct HittableList {
-    pub objects: Vec<Box<dyn Hittable + Sync>>,
+    pub objects: Vec<Box<dyn Hittable + Send + Sync>>,
+}
+
+impl Hittable for HittableList {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord<'_>> {
+        let objects = self.objects.iter().enumerate();
+        closest_hit(objects.map(|(id, object)| (id as u32, object)), ray, interval)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.objects
+            .iter()
+            .fold(Aabb::EMPTY, |acc, object| acc.union(&object.bounding_box()))
+    }
+
+    /// Picks one of the objects uniformly, so the density is the average of
+    /// theirs.
+    fn pdf_value(&self, origin: DVec3, direction: DVec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction))
+            .sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random(&self, origin: DVec3) -> DVec3 {
+        if self.objects.is_empty() {
+            return DVec3::X;
+        }
+        let index =
+            ((random_f64() * self.objects.len() as f64) as usize).min(self.objects.len() - 1);
+        self.objects[index].random(origin)
+    }
 }
 
-impl HittableList {
-    pub fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
-        let (_closest_t, hit_record) =
-            self.objects
-                .iter()
-                .fold((interval.end, None), |acc, object| {
-                    if let Some(hit_rec) = object.hit(ray, interval.start..acc.0) {
-                        // hit something
-                        (hit_rec.t, Some(hit_rec))
-                    } else {
-                        acc
-                    }
-                });
-
-        hit_record
+/// Lets one object be in several lists, e.g. a light that is also part of
+/// the world.
+impl<T: Hittable + ?Sized> Hittable for Arc<T> {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord<'_>> {
+        (**self).hit(ray, interval)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (**self).bounding_box()
+    }
+
+    fn pdf_value(&self, origin: DVec3, direction: DVec3) -> f64 {
+        (**self).pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: DVec3) -> DVec3 {
+        (**self).random(origin)
     }
 }
 
-pub struct HitRecord {
+/// Tests every object and keeps the hit nearest to the ray origin, tagged
+/// with the id paired with the object that was hit.
+pub(crate) fn closest_hit<'a>(
+    objects: impl Iterator<Item = (u32, &'a Box<dyn Hittable + Send + Sync>)>,
+    ray: &Ray,
+    interval: Range<f64>,
+) -> Option<HitRecord<'a>> {
+    let (_closest_t, hit_record) = objects.fold((interval.end, None), |acc, (id, object)| {
+        if let Some(mut hit_rec) = object.hit(ray, interval.start..acc.0) {
+            // hit something
+            hit_rec.object_id = id;
+            (hit_rec.t, Some(hit_rec))
+        } else {
+            acc
+        }
+    });
+
+    hit_record
+}
+
+pub struct HitRecord<'a> {
     pub point: DVec3,
     pub outward_normal: DVec3,
     pub t: f64, // position along the ray
     pub front_face: bool,
-    pub material: Material,
+    pub material: &'a Material,
+    /// Surface coordinates of the hit point, each in [0, 1].
+    pub u: f64,
+    pub v: f64,
+    /// Index of the object that was hit in the outermost `HittableList`
+    /// (or the list a `BvhNode` was built from).
+    pub object_id: u32,
 }
 
-impl HitRecord {
+impl<'a> HitRecord<'a> {
     pub fn new(
         point: DVec3,
         mut outward_normal: DVec3,
         t: f64,
         ray: &Ray,
-        material: Material,
+        material: &'a Material,
     ) -> Self {
         let (normal, front_face) = HitRecord::calculate_face_normal(ray, &mut outward_normal);
         Self {
@@ -53,9 +133,19 @@ impl HitRecord {
             t,
             front_face,
             material,
+            u: 0.0,
+            v: 0.0,
+            object_id: 0,
         }
     }
 
+    /// Sets the surface coordinates of the hit point.
+    pub fn with_uv(mut self, u: f64, v: f64) -> Self {
+        self.u = u;
+        self.v = v;
+        self
+    }
+
     /// Calculates if the outward normal is front or back facing.
     /// Expects outward normal to be of unit length.
     /// Returns the normal negated if it is inside
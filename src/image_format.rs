@@ -0,0 +1,312 @@
+//! Encoders for rendered images.
+//!
+//! Pixels are handed over in linear RGB, row by row from the top left.
+//! Integer formats go through a `ToneMap` and are quantised; float formats
+//! store the linear values untouched.
+
+pub mod exr;
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use glam::DVec3;
+use image::{
+    codecs::{hdr::HdrEncoder, png::PngEncoder},
+    ExtendedColorType, ImageEncoder, Rgb,
+};
+
+use crate::{
+    image_format::exr::{write_exr, ExrChannel, ExrPixelType},
+    tonemap::ToneMap,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Plain text PPM (P3), one pixel per line.
+    PpmAscii,
+    /// Binary PPM (P6) with 8 bits per channel.
+    PpmBinary,
+    /// PNG with 8 bits per channel.
+    Png8,
+    /// PNG with 16 bits per channel.
+    Png16,
+    /// Portable float map: lossless 32 bit float RGB.
+    Pfm,
+    /// OpenEXR with 16 bit float channels.
+    ExrHalf,
+    /// OpenEXR with 32 bit float channels.
+    ExrFloat,
+    /// Radiance RGBE. Compact, but negative values are lost.
+    Hdr,
+}
+
+impl ImageFormat {
+    /// Picks a format from a file extension: `ppm` (binary), `png` (8 bit),
+    /// `pfm`, `exr` (half float) or `hdr`.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "ppm" => Some(ImageFormat::PpmBinary),
+            "png" => Some(ImageFormat::Png8),
+            "pfm" => Some(ImageFormat::Pfm),
+            "exr" => Some(ImageFormat::ExrHalf),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+
+    /// Whether the format keeps values above 1 instead of clamping them.
+    pub fn is_float(&self) -> bool {
+        matches!(
+            self,
+            ImageFormat::Pfm | ImageFormat::ExrHalf | ImageFormat::ExrFloat | ImageFormat::Hdr
+        )
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        path.as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Self::from_extension)
+    }
+
+    /// Encodes the pixels. `tone_map` only applies to integer formats.
+    pub fn encode(
+        &self,
+        writer: &mut impl Write,
+        width: u32,
+        height: u32,
+        pixels: &[DVec3],
+        tone_map: &ToneMap,
+    ) -> io::Result<()> {
+        if pixels.len() != width as usize * height as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pixel count does not match the image size",
+            ));
+        }
+
+        match self {
+            ImageFormat::PpmAscii => {
+                writeln!(writer, "P3")?;
+                writeln!(writer, "{} {}", width, height)?;
+                writeln!(writer, "{}", u8::MAX)?;
+                for (index, pixel) in pixels.iter().enumerate() {
+                    let [r, g, b] = to_u8(*pixel, index, tone_map);
+                    writeln!(writer, "{} {} {}", r, g, b)?;
+                }
+            }
+            ImageFormat::PpmBinary => {
+                write!(writer, "P6\n{} {}\n{}\n", width, height, u8::MAX)?;
+                let bytes = to_u8_bytes(pixels, tone_map);
+                writer.write_all(&bytes)?;
+            }
+            ImageFormat::Png8 => {
+                let bytes = to_u8_bytes(pixels, tone_map);
+                PngEncoder::new(writer)
+                    .write_image(&bytes, width, height, ExtendedColorType::Rgb8)
+                    .map_err(io::Error::other)?;
+            }
+            ImageFormat::Png16 => {
+                // the encoder expects 16 bit samples in native byte order
+                let bytes: Vec<u8> = pixels
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(index, pixel)| tone_map.quantize(*pixel, index, u16::MAX as u32))
+                    .flat_map(|value| (value as u16).to_ne_bytes())
+                    .collect();
+                PngEncoder::new(writer)
+                    .write_image(&bytes, width, height, ExtendedColorType::Rgb16)
+                    .map_err(io::Error::other)?;
+            }
+            ImageFormat::Pfm => {
+                // negative scale marks little endian data
+                write!(writer, "PF\n{} {}\n-1.0\n", width, height)?;
+                // rows are stored bottom to top
+                for row in pixels.chunks(width.max(1) as usize).rev() {
+                    for pixel in row {
+                        for channel in pixel.to_array() {
+                            writer.write_all(&(channel as f32).to_le_bytes())?;
+                        }
+                    }
+                }
+            }
+            ImageFormat::ExrHalf | ImageFormat::ExrFloat => {
+                let pixel_type = if *self == ImageFormat::ExrHalf {
+                    ExrPixelType::Half
+                } else {
+                    ExrPixelType::Float
+                };
+                let channels = ExrChannel::rgb("", pixel_type, pixels).to_vec();
+                write_exr(writer, width, height, channels)?;
+            }
+            ImageFormat::Hdr => {
+                let rgb: Vec<Rgb<f32>> = pixels
+                    .iter()
+                    .map(|pixel| Rgb(pixel.max(DVec3::ZERO).as_vec3().to_array()))
+                    .collect();
+                HdrEncoder::new(writer)
+                    .encode(&rgb, width as usize, height as usize)
+                    .map_err(io::Error::other)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the pixels to `path` in the format matching its extension.
+pub fn write_image(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    pixels: &[DVec3],
+    tone_map: &ToneMap,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let format = ImageFormat::from_path(path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported image extension: {}", path.display()),
+        )
+    })?;
+
+    write_image_as(path, format, width, height, pixels, tone_map)
+}
+
+/// Writes the pixels to `path` in the given format, whatever its extension.
+pub fn write_image_as(
+    path: impl AsRef<Path>,
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    pixels: &[DVec3],
+    tone_map: &ToneMap,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    format.encode(&mut writer, width, height, pixels, tone_map)?;
+    writer.flush()
+}
+
+fn to_u8(pixel: DVec3, index: usize, tone_map: &ToneMap) -> [u8; 3] {
+    tone_map
+        .quantize(pixel, index, u8::MAX as u32)
+        .map(|value| value as u8)
+}
+
+fn to_u8_bytes(pixels: &[DVec3], tone_map: &ToneMap) -> Vec<u8> {
+    pixels
+        .iter()
+        .enumerate()
+        .flat_map(|(index, pixel)| to_u8(*pixel, index, tone_map))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXELS: [DVec3; 2] = [DVec3::new(1.0, 0.25, 0.0), DVec3::new(0.0, 0.0, 4.0)];
+
+    fn encode(format: ImageFormat) -> Vec<u8> {
+        let mut bytes = vec![];
+        format
+            .encode(&mut bytes, 2, 1, &PIXELS, &ToneMap::default())
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(
+            ImageFormat::from_path("out/image.PNG"),
+            Some(ImageFormat::Png8)
+        );
+        assert_eq!(
+            ImageFormat::from_path("image.ppm"),
+            Some(ImageFormat::PpmBinary)
+        );
+        assert_eq!(ImageFormat::from_path("image"), None);
+        assert_eq!(ImageFormat::from_path("image.gif"), None);
+    }
+
+    #[test]
+    fn test_ppm() {
+        // 0.25 is 0.537 in sRGB
+        assert_eq!(
+            String::from_utf8(encode(ImageFormat::PpmAscii)).unwrap(),
+            "P3\n2 1\n255\n255 137 0\n0 0 255\n"
+        );
+        assert_eq!(
+            encode(ImageFormat::PpmBinary),
+            b"P6\n2 1\n255\n\xff\x89\x00\x00\x00\xff"
+        );
+    }
+
+    #[test]
+    fn test_tone_map_skips_float_formats() {
+        let tone_map = ToneMap {
+            exposure: -1.0,
+            ..ToneMap::default()
+        };
+        let encode_with = |format: ImageFormat| {
+            let mut bytes = vec![];
+            format.encode(&mut bytes, 2, 1, &PIXELS, &tone_map).unwrap();
+            bytes
+        };
+        assert_eq!(encode_with(ImageFormat::Pfm), encode(ImageFormat::Pfm));
+        // 1.0 halved is 0.5, or 188 once sRGB encoded
+        assert_eq!(encode_with(ImageFormat::PpmBinary)[11], 188);
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let png8 = image::load_from_memory(&encode(ImageFormat::Png8)).unwrap();
+        assert_eq!(png8.to_rgb8().into_raw(), [255, 137, 0, 0, 0, 255]);
+
+        let png16 = image::load_from_memory(&encode(ImageFormat::Png16)).unwrap();
+        assert_eq!(png16.to_rgb16().into_raw(), [65535, 35199, 0, 0, 0, 65535]);
+    }
+
+    #[test]
+    fn test_pfm_keeps_linear_values() {
+        let bytes = encode(ImageFormat::Pfm);
+        let header = b"PF\n2 1\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+
+        let floats: Vec<f32> = bytes[header.len()..]
+            .chunks(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        assert_eq!(floats, [1.0, 0.25, 0.0, 0.0, 0.0, 4.0]);
+    }
+
+    #[test]
+    fn test_hdr_round_trip() {
+        let hdr = image::load_from_memory(&encode(ImageFormat::Hdr)).unwrap();
+        let floats = hdr.to_rgb32f().into_raw();
+        // RGBE shares one exponent per pixel, so small channels lose bits
+        let expected = [1.0, 0.25, 0.0, 0.0, 0.0, 4.0];
+        for (value, expected) in floats.iter().zip(expected) {
+            assert!((value - expected).abs() < 0.01, "{} != {}", value, expected);
+        }
+    }
+
+    #[test]
+    fn test_exr_header() {
+        let bytes = encode(ImageFormat::ExrFloat);
+        assert_eq!(bytes[..4], [0x76, 0x2f, 0x31, 0x01]);
+        assert!(ImageFormat::from_path("render.exr").unwrap().is_float());
+        assert!(!ImageFormat::Png16.is_float());
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let mut bytes = vec![];
+        assert!(ImageFormat::PpmBinary
+            .encode(&mut bytes, 3, 1, &PIXELS, &ToneMap::default())
+            .is_err());
+    }
+}
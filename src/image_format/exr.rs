@@ -0,0 +1,245 @@
+//! Minimal OpenEXR writer: single part, scanline, uncompressed.
+//!
+//! That is enough for every EXR reader to load the file, and keeps the
+//! renderer free of a compression dependency.
+
+use std::io::{self, Write};
+
+use glam::DVec3;
+use half::f16;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+/// Version 2, single part scanline file.
+const VERSION: [u8; 4] = [2, 0, 0, 0];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExrPixelType {
+    /// 16 bit float. Plenty for colours, but integers above 2048 lose
+    /// precision.
+    Half,
+    /// 32 bit float. Integers above 2^24 lose precision.
+    Float,
+    /// 32 bit unsigned integer, for ids.
+    Uint,
+}
+
+impl ExrPixelType {
+    fn id(self) -> i32 {
+        match self {
+            ExrPixelType::Uint => 0,
+            ExrPixelType::Half => 1,
+            ExrPixelType::Float => 2,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ExrPixelType::Half => 2,
+            ExrPixelType::Float | ExrPixelType::Uint => 4,
+        }
+    }
+}
+
+/// One channel of an EXR image, with a value per pixel.
+#[derive(Clone, Debug)]
+pub struct ExrChannel {
+    /// Layers are written as `layer.R`, `layer.G`, ...
+    pub name: String,
+    pub pixel_type: ExrPixelType,
+    /// Converted to `pixel_type` when written.
+    pub values: Vec<f64>,
+}
+
+impl ExrChannel {
+    /// Splits RGB pixels into `R`, `G` and `B` channels, prefixed with
+    /// `layer.` unless the layer is empty.
+    pub fn rgb(layer: &str, pixel_type: ExrPixelType, pixels: &[DVec3]) -> [ExrChannel; 3] {
+        let channel = |suffix: &str, axis: usize| ExrChannel {
+            name: channel_name(layer, suffix),
+            pixel_type,
+            values: pixels.iter().map(|pixel| pixel[axis]).collect(),
+        };
+        [channel("R", 0), channel("G", 1), channel("B", 2)]
+    }
+
+    /// A single `Y` channel taken from the red component of the pixels,
+    /// for data such as depth or ids.
+    pub fn luminance(layer: &str, pixel_type: ExrPixelType, pixels: &[DVec3]) -> ExrChannel {
+        ExrChannel {
+            name: channel_name(layer, "Y"),
+            pixel_type,
+            values: pixels.iter().map(|pixel| pixel.x).collect(),
+        }
+    }
+}
+
+fn channel_name(layer: &str, suffix: &str) -> String {
+    if layer.is_empty() {
+        suffix.to_string()
+    } else {
+        format!("{}.{}", layer, suffix)
+    }
+}
+
+/// Writes an EXR image with the given channels, in any order.
+pub fn write_exr(
+    writer: &mut impl Write,
+    width: u32,
+    height: u32,
+    mut channels: Vec<ExrChannel>,
+) -> io::Result<()> {
+    let pixel_count = width as usize * height as usize;
+    if channels
+        .iter()
+        .any(|channel| channel.values.len() != pixel_count)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "pixel count does not match the image size",
+        ));
+    }
+    // readers expect the channel list sorted by name
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut header = vec![];
+    header.extend_from_slice(&MAGIC);
+    header.extend_from_slice(&VERSION);
+
+    let mut channel_list = vec![];
+    for channel in &channels {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&channel.pixel_type.id().to_le_bytes());
+        // pLinear and three reserved bytes
+        channel_list.extend_from_slice(&[0; 4]);
+        // no subsampling
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    attribute(&mut header, "channels", "chlist", &channel_list);
+
+    attribute(&mut header, "compression", "compression", &[0]);
+    let window = [0, 0, width as i32 - 1, height as i32 - 1];
+    let window: Vec<u8> = window.iter().flat_map(|v| v.to_le_bytes()).collect();
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    // increasing y
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+    writer.write_all(&header)?;
+
+    // one scanline per block: the y coordinate, the data size, then every
+    // channel's values for the line
+    let line_size: usize = channels
+        .iter()
+        .map(|channel| channel.pixel_type.size() * width as usize)
+        .sum();
+    let block_size = 8 + line_size;
+    let table_end = header.len() + 8 * height as usize;
+    for y in 0..height as usize {
+        let offset = (table_end + y * block_size) as u64;
+        writer.write_all(&offset.to_le_bytes())?;
+    }
+
+    let mut block = Vec::with_capacity(block_size);
+    for y in 0..height as usize {
+        block.clear();
+        block.extend_from_slice(&(y as i32).to_le_bytes());
+        block.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for channel in &channels {
+            let line = &channel.values[y * width as usize..(y + 1) * width as usize];
+            for value in line {
+                match channel.pixel_type {
+                    ExrPixelType::Half => {
+                        block.extend_from_slice(&f16::from_f64(*value).to_le_bytes())
+                    }
+                    ExrPixelType::Float => block.extend_from_slice(&(*value as f32).to_le_bytes()),
+                    ExrPixelType::Uint => block.extend_from_slice(&(*value as u32).to_le_bytes()),
+                }
+            }
+        }
+        writer.write_all(&block)?;
+    }
+
+    Ok(())
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_i32(bytes: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_layout() {
+        let pixels = [DVec3::new(1.0, 0.5, 0.25), DVec3::new(2.0, 4.0, 8.0)];
+        let mut channels = ExrChannel::rgb("", ExrPixelType::Half, &pixels).to_vec();
+        channels.push(ExrChannel::luminance("depth", ExrPixelType::Float, &pixels));
+
+        let mut bytes = vec![];
+        write_exr(&mut bytes, 1, 2, channels).unwrap();
+        assert_eq!(bytes[..4], MAGIC);
+
+        // the offset table follows the header and points at the blocks
+        let header_end = bytes.len() - 2 * (8 + 3 * 2 + 4) - 2 * 8;
+        let first = u64::from_le_bytes(bytes[header_end..header_end + 8].try_into().unwrap());
+        let first = first as usize;
+        assert_eq!(first, header_end + 16);
+        assert_eq!(read_i32(&bytes, first), 0);
+        assert_eq!(read_i32(&bytes, first + 4), 3 * 2 + 4);
+
+        // channels are sorted: B, G, R, depth.Y
+        let data = &bytes[first + 8..];
+        let half = |at: usize| f16::from_le_bytes([data[at], data[at + 1]]).to_f32();
+        assert_eq!((half(0), half(2), half(4)), (0.25, 0.5, 1.0));
+        assert_eq!(f32::from_le_bytes(data[6..10].try_into().unwrap()), 1.0);
+    }
+
+    #[test]
+    fn test_uint_keeps_large_ids() {
+        let id = 0xDEAD_BEEF_u32;
+        let channels = vec![ExrChannel::luminance(
+            "material_id",
+            ExrPixelType::Uint,
+            &[DVec3::splat(id as f64)],
+        )];
+        let mut bytes = vec![];
+        write_exr(&mut bytes, 1, 1, channels).unwrap();
+        assert_eq!(bytes[bytes.len() - 4..], id.to_le_bytes());
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let channels = vec![ExrChannel::luminance(
+            "",
+            ExrPixelType::Float,
+            &[DVec3::ONE],
+        )];
+        assert!(write_exr(&mut vec![], 2, 2, channels).is_err());
+    }
+}
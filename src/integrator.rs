@@ -0,0 +1,285 @@
+pub mod debug;
+
+use std::fmt;
+
+use glam::DVec3;
+
+use crate::{
+    camera::Background,
+    fastrand::random_f64,
+    hittable::{HitRecord, Hittable, HittableList},
+    ray::Ray,
+};
+
+/// Everything an integrator can see of the scene being rendered.
+#[derive(Clone, Copy)]
+pub struct SceneView<'a> {
+    pub world: &'a (dyn Hittable + Sync),
+    /// Emissive objects worth sampling directly. They are also in `world`.
+    pub lights: &'a HittableList,
+    pub background: Background,
+}
+
+/// Estimates the light arriving along camera rays.
+///
+/// The camera calls `radiance` once per sample. Random numbers come from
+/// `fastrand::random_f64`, which the camera points at its sampler for the
+/// duration of every sample, so integrators stay deterministic as long as
+/// they draw nothing else.
+pub trait Integrator: fmt::Debug + Send + Sync {
+    fn radiance(&self, ray: &Ray, scene: &SceneView) -> DVec3;
+
+    /// Like `radiance`, but also passes the first hit along `ray` (or the
+    /// miss) to `first_hit`, for AOVs. Integrators that intersect the camera
+    /// ray anyway should hand over that hit instead of intersecting twice.
+    fn radiance_with_first_hit(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        first_hit: &mut dyn FnMut(Option<&HitRecord>),
+    ) -> DVec3 {
+        first_hit(scene.world.hit(ray, 0.001..f64::INFINITY).as_ref());
+        self.radiance(ray, scene)
+    }
+}
+
+/// Bounces a path makes before `SimplePathIntegrator` starts playing
+/// Russian roulette, unless told otherwise.
+pub const DEFAULT_ROULETTE_DEPTH: u32 = 3;
+
+/// Unidirectional path tracer. At every diffuse bounce it also samples
+/// `lights` directly and combines both strategies with multiple importance
+/// sampling.
+#[derive(Clone, Debug)]
+pub struct SimplePathIntegrator {
+    /// Paths are cut off after this many bounces.
+    pub max_depth: u32,
+    /// After this many bounces a path only continues with a probability
+    /// matching its throughput, and survivors are weighted up to
+    /// compensate. Dim paths end early without biasing the image.
+    /// `u32::MAX` turns this off.
+    pub roulette_depth: u32,
+}
+
+impl SimplePathIntegrator {
+    pub fn new(max_depth: u32) -> Self {
+        Self {
+            max_depth,
+            roulette_depth: DEFAULT_ROULETTE_DEPTH,
+        }
+    }
+
+    pub fn with_roulette_depth(mut self, roulette_depth: u32) -> Self {
+        self.roulette_depth = roulette_depth;
+        self
+    }
+}
+
+impl Integrator for SimplePathIntegrator {
+    fn radiance(&self, ray: &Ray, scene: &SceneView) -> DVec3 {
+        self.radiance_with_first_hit(ray, scene, &mut |_| {})
+    }
+
+    fn radiance_with_first_hit(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        first_hit: &mut dyn FnMut(Option<&HitRecord>),
+    ) -> DVec3 {
+        let mut radiance = DVec3::ZERO;
+        // how much of the light arriving along `ray` reaches the camera
+        let mut throughput = DVec3::ONE;
+        let mut ray = Ray::new(ray.origin, ray.direction);
+        // the density the previous bounce sampled `ray` with; `None` for
+        // camera rays and specular bounces, whose emission light sampling
+        // can't account for
+        let mut bsdf_pdf = None;
+
+        for bounce in 0..self.max_depth {
+            let hit = scene.world.hit(&ray, 0.001..f64::INFINITY);
+            if bounce == 0 {
+                first_hit(hit.as_ref());
+            }
+            let Some(hit_record) = hit else {
+                // render background if we don't hit anything
+                radiance += throughput * scene.background.color(&ray);
+                break;
+            };
+
+            let mut emitted = hit_record.material.emitted(&hit_record);
+            if let Some(bsdf_pdf) = bsdf_pdf {
+                if emitted != DVec3::ZERO && is_on_light(scene.lights, &ray, &hit_record) {
+                    // light sampling at the previous bounce could have found
+                    // this point too, so it only gets its share
+                    let light_pdf = scene.lights.pdf_value(ray.origin, ray.direction);
+                    emitted *= power_heuristic(bsdf_pdf, light_pdf);
+                }
+            }
+            radiance += throughput * emitted;
+
+            let Some(scatter) = hit_record.material.scatter(&ray, &hit_record) else {
+                break;
+            };
+            if scatter.pdf.is_some() {
+                radiance += throughput * sample_lights(&hit_record, scene);
+            }
+
+            throughput *= scatter.attenuation;
+            if bounce + 1 >= self.roulette_depth {
+                let survival = throughput.max_element().min(1.0);
+                if survival <= 0.0 || random_f64() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+
+            bsdf_pdf = scatter.pdf;
+            ray = scatter.ray;
+        }
+
+        radiance
+    }
+}
+
+/// Direct light reaching `hit_record` from a point picked on the scene's
+/// lights, weighted against the chance of the BSDF sampling the same
+/// direction.
+pub fn sample_lights(hit_record: &HitRecord, scene: &SceneView) -> DVec3 {
+    let lights = scene.lights;
+    if lights.objects.is_empty() {
+        return DVec3::ZERO;
+    }
+
+    let direction = lights.random(hit_record.point);
+    let light_pdf = lights.pdf_value(hit_record.point, direction);
+    let bsdf = hit_record.material.eval(hit_record, direction);
+    if light_pdf <= 0.0 || bsdf == DVec3::ZERO {
+        return DVec3::ZERO;
+    }
+
+    // shadow ray: the light only counts if nothing is in the way
+    let shadow_ray = Ray::new(hit_record.point, direction);
+    let Some(light_hit) = scene.world.hit(&shadow_ray, 0.001..f64::INFINITY) else {
+        return DVec3::ZERO;
+    };
+    if !is_on_light(lights, &shadow_ray, &light_hit) {
+        return DVec3::ZERO;
+    }
+
+    let emitted = light_hit.material.emitted(&light_hit);
+    let bsdf_pdf = hit_record.material.scattering_pdf(hit_record, direction);
+    bsdf * emitted * power_heuristic(light_pdf, bsdf_pdf) / light_pdf
+}
+
+/// Veach's power heuristic (beta = 2): the weight of a sample drawn with
+/// density `pdf` when another strategy would have drawn it with `other_pdf`.
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+/// Whether `hit_record`, the closest hit along `ray`, lies on one of `lights`
+/// rather than on some other emitter.
+fn is_on_light(lights: &HittableList, ray: &Ray, hit_record: &HitRecord) -> bool {
+    lights
+        .hit(ray, 0.001..f64::INFINITY)
+        .is_some_and(|light_hit| (light_hit.t - hit_record.t).abs() <= 1e-6 * hit_record.t.max(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fastrand::seed_thread_rng, material::Material, shapes::sphere::Sphere, texture::Texture,
+    };
+
+    #[test]
+    fn test_power_heuristic() {
+        assert_eq!(power_heuristic(1.0, 0.0), 1.0);
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+        assert!((power_heuristic(1.0, 2.0) + power_heuristic(2.0, 1.0) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_path_sees_emitter_and_background() {
+        let mut world = HittableList::default();
+        world.objects.push(Box::new(Sphere {
+            center: DVec3::new(0., 0., -2.),
+            radius: 1.0,
+            material: Material::DiffuseLight {
+                emit: DVec3::new(1., 2., 3.),
+            },
+        }));
+        world.objects.push(Box::new(Sphere {
+            center: DVec3::new(0., 0., 2.),
+            radius: 1.0,
+            material: Material::Lambertian {
+                albedo: Texture::Solid(DVec3::ONE),
+            },
+        }));
+        let lights = HittableList::default();
+        let scene = SceneView {
+            world: &world,
+            lights: &lights,
+            background: Background::Solid(DVec3::splat(0.25)),
+        };
+        let integrator = SimplePathIntegrator::new(4);
+
+        let at_light = Ray::new(DVec3::ZERO, DVec3::new(0., 0., -1.));
+        let at_sky = Ray::new(DVec3::ZERO, DVec3::Y);
+        assert_eq!(
+            integrator.radiance(&at_light, &scene),
+            DVec3::new(1., 2., 3.)
+        );
+        assert_eq!(integrator.radiance(&at_sky, &scene), DVec3::splat(0.25));
+        assert_eq!(
+            SimplePathIntegrator::new(0).radiance(&at_sky, &scene),
+            DVec3::ZERO
+        );
+
+        // the camera ray's hit is handed out once, for AOVs
+        let mut first_hits = vec![];
+        let color = integrator.radiance_with_first_hit(&at_light, &scene, &mut |hit| {
+            first_hits.push(hit.map(|hit| hit.t))
+        });
+        assert_eq!(color, DVec3::new(1., 2., 3.));
+        assert_eq!(first_hits, [Some(1.0)]);
+        integrator.radiance_with_first_hit(&at_sky, &scene, &mut |hit| assert!(hit.is_none()));
+    }
+
+    #[test]
+    fn test_roulette_is_unbiased() {
+        // every bounce off a convex grey sphere escapes to the background,
+        // so the exact answer is albedo * background
+        let mut world = HittableList::default();
+        world.objects.push(Box::new(Sphere {
+            center: DVec3::new(0., 0., -2.),
+            radius: 1.0,
+            material: Material::Lambertian {
+                albedo: Texture::Solid(DVec3::splat(0.5)),
+            },
+        }));
+        let lights = HittableList::default();
+        let scene = SceneView {
+            world: &world,
+            lights: &lights,
+            background: Background::Solid(DVec3::ONE),
+        };
+        let integrator = SimplePathIntegrator::new(8).with_roulette_depth(0);
+        let ray = Ray::new(DVec3::ZERO, DVec3::new(0., 0., -1.));
+
+        seed_thread_rng(7);
+        let samples: Vec<f64> = (0..4000)
+            .map(|_| integrator.radiance(&ray, &scene).x)
+            .collect();
+        // paths either die or come back weighted up
+        assert!(samples
+            .iter()
+            .all(|sample| *sample == 0.0 || *sample == 1.0));
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        assert!((mean - 0.5).abs() < 0.03, "mean {}", mean);
+    }
+}
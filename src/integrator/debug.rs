@@ -0,0 +1,155 @@
+use glam::DVec3;
+
+use crate::{fastrand::mix64, hittable::HitRecord, ray::Ray};
+
+use super::{Integrator, SceneView};
+
+/// What `DebugIntegrator` shows for the first surface each camera ray hits.
+/// Rays that miss everything are black.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DebugMode {
+    /// Outward surface normal, mapped from [-1, 1] to [0, 1] per axis.
+    Normal,
+    /// Distance from the ray origin, divided by `max_distance` so the far
+    /// end of the scene is white.
+    Depth { max_distance: f64 },
+    /// Surface colour without any lighting.
+    Albedo,
+    /// Green where the ray hit the front face, red where it hit the back.
+    Facing,
+    /// A distinct colour for every top-level object.
+    ObjectId,
+}
+
+/// Shades the first hit only, to diagnose geometry, normals and textures
+/// without waiting for a converged render.
+#[derive(Clone, Debug)]
+pub struct DebugIntegrator {
+    pub mode: DebugMode,
+}
+
+impl DebugIntegrator {
+    pub fn new(mode: DebugMode) -> Self {
+        Self { mode }
+    }
+
+    fn shade(&self, ray: &Ray, hit_record: &HitRecord) -> DVec3 {
+        match self.mode {
+            DebugMode::Normal => 0.5 * (outward_normal(hit_record) + DVec3::ONE),
+            DebugMode::Depth { max_distance } => {
+                let distance = hit_record.t * ray.direction.length();
+                DVec3::splat(distance / max_distance)
+            }
+            DebugMode::Albedo => hit_record.material.albedo(hit_record),
+            DebugMode::Facing => {
+                if hit_record.front_face {
+                    DVec3::Y
+                } else {
+                    DVec3::X
+                }
+            }
+            DebugMode::ObjectId => id_color(hit_record.object_id),
+        }
+    }
+}
+
+impl Integrator for DebugIntegrator {
+    fn radiance(&self, ray: &Ray, scene: &SceneView) -> DVec3 {
+        self.radiance_with_first_hit(ray, scene, &mut |_| {})
+    }
+
+    fn radiance_with_first_hit(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        first_hit: &mut dyn FnMut(Option<&HitRecord>),
+    ) -> DVec3 {
+        let hit = scene.world.hit(ray, 0.001..f64::INFINITY);
+        first_hit(hit.as_ref());
+        match hit {
+            Some(hit_record) => self.shade(ray, &hit_record),
+            None => DVec3::ZERO,
+        }
+    }
+}
+
+/// The normal pointing out of the surface, whichever side the ray came from.
+pub fn outward_normal(hit_record: &HitRecord) -> DVec3 {
+    if hit_record.front_face {
+        hit_record.outward_normal
+    } else {
+        -hit_record.outward_normal
+    }
+}
+
+/// A bright, pseudo random colour that neighbouring ids are unlikely to share.
+pub fn id_color(id: u32) -> DVec3 {
+    let hash = mix64(id as u64 + 1);
+    let channel = |shift: u32| ((hash >> shift) & 0xff) as f64 / 255.0;
+    0.2 + 0.8 * DVec3::new(channel(0), channel(8), channel(16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::Background,
+        hittable::HittableList,
+        material::Material,
+        shapes::{quad::Quad, sphere::Sphere},
+        texture::Texture,
+    };
+
+    fn scene() -> HittableList {
+        let mut world = HittableList::default();
+        world.objects.push(Box::new(Sphere {
+            center: DVec3::new(0., 0., -3.),
+            radius: 1.0,
+            material: Material::Lambertian {
+                albedo: Texture::Solid(DVec3::new(0.2, 0.4, 0.6)),
+            },
+        }));
+        // faces away from the origin
+        world.objects.push(Box::new(Quad::new(
+            DVec3::new(-1., -1., 3.),
+            DVec3::X * 2.0,
+            DVec3::Y * 2.0,
+            Material::Dielectric {
+                refractive_index: 1.5,
+            },
+        )));
+        world
+    }
+
+    fn shade(mode: DebugMode, direction: DVec3) -> DVec3 {
+        let world = scene();
+        let lights = HittableList::default();
+        let view = SceneView {
+            world: &world,
+            lights: &lights,
+            background: Background::Sky,
+        };
+        DebugIntegrator::new(mode).radiance(&Ray::new(DVec3::ZERO, direction), &view)
+    }
+
+    #[test]
+    fn test_modes() {
+        let ahead = -DVec3::Z;
+        assert_eq!(shade(DebugMode::Normal, ahead), DVec3::new(0.5, 0.5, 1.0));
+        assert_eq!(
+            shade(DebugMode::Depth { max_distance: 4.0 }, ahead * 2.0),
+            DVec3::splat(0.5)
+        );
+        assert_eq!(shade(DebugMode::Albedo, ahead), DVec3::new(0.2, 0.4, 0.6));
+        assert_eq!(shade(DebugMode::Facing, ahead), DVec3::Y);
+        assert_eq!(shade(DebugMode::Facing, DVec3::Z), DVec3::X);
+        assert_eq!(shade(DebugMode::Normal, DVec3::Y), DVec3::ZERO);
+    }
+
+    #[test]
+    fn test_object_ids_differ() {
+        assert_eq!(shade(DebugMode::ObjectId, -DVec3::Z), id_color(0));
+        assert_eq!(shade(DebugMode::ObjectId, DVec3::Z), id_color(1));
+        assert_ne!(id_color(0), id_color(1));
+    }
+}
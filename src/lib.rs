@@ -1,7 +1,24 @@
-#![feature(thread_id_value)]
+pub mod aabb;
+pub mod accumulator;
+pub mod adaptive;
+pub mod aov;
+pub mod bvh;
 pub mod camera;
+pub mod checkpoint;
+pub mod distributed;
 pub mod fastrand;
+pub mod film;
+pub mod filter;
+pub mod framebuffer;
 pub mod hittable;
+pub mod image_format;
+pub mod integrator;
 pub mod material;
+pub mod obj;
+pub mod partial;
 pub mod ray;
+pub mod sampler;
+pub mod scene;
 pub mod shapes;
+pub mod texture;
+pub mod tonemap;
@@ -1,15 +1,415 @@
-#![feature(thread_id_value)]
-use std::io;
+use std::{
+    error::Error,
+    fs,
+    net::{TcpListener, ToSocketAddrs},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 use aurora::{
-    camera::CameraBuilder, hittable::HittableList, material::Material, shapes::sphere::Sphere,
+    aabb::Aabb,
+    accumulator::Accumulator,
+    aov::Aov,
+    bvh::BvhNode,
+    camera::{Camera, CameraBuilder, ProgressiveOptions},
+    checkpoint::{load_checkpoint, save_checkpoint},
+    distributed::{render_distributed, serve, DistributedOptions, DistributedScene},
+    filter::{check_radius, FilterKind},
+    hittable::{Hittable, HittableList},
+    integrator::debug::{DebugIntegrator, DebugMode},
+    material::Material,
+    partial::PartialRender,
+    sampler::SamplerKind,
+    scene::load_scene,
+    shapes::sphere::Sphere,
+    texture::Texture,
+    tonemap::{ToneCurve, ToneMap},
 };
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
 use glam::DVec3;
-use rand::Rng;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+#[derive(Parser)]
+#[command(name = "aurora", version, about = "A small path tracer")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Render a scene file to an image.
+    Render {
+        /// Path to a TOML scene description.
+        scene: PathBuf,
+        /// Instead of lighting the scene, show one property of the first
+        /// surface each ray hits.
+        #[arg(long, value_enum)]
+        debug: Option<DebugView>,
+        /// Comma separated AOVs to write next to the image, or `all`:
+        /// depth, normal, albedo, object_id, material_id, sample_count.
+        #[arg(long, value_delimiter = ',', value_parser = parse_aovs)]
+        aov: Vec<Vec<Aov>>,
+        #[command(flatten)]
+        progressive: ProgressiveArgs,
+        #[command(flatten)]
+        distributed: DistributedArgs,
+        #[command(flatten)]
+        options: RenderOptions,
+    },
+    /// Print what a scene file contains without rendering it.
+    Info {
+        /// Path to a TOML scene description.
+        scene: PathBuf,
+        #[command(flatten)]
+        options: RenderOptions,
+    },
+    /// Render the built-in spheres scene and report how long it took.
+    Bench {
+        #[command(flatten)]
+        options: RenderOptions,
+    },
+    /// Combine partial renders of the same scene, made with different
+    /// seeds, into one image.
+    Merge {
+        /// Partial renders written with `render --partial`.
+        #[arg(required = true)]
+        partials: Vec<PathBuf>,
+        /// Where to write the image. The extension picks the format;
+        /// `.partial` writes another partial render.
+        #[arg(long, short = 'o')]
+        output: PathBuf,
+        #[command(flatten)]
+        tone_map: ToneMapArgs,
+    },
+    /// Render samples for coordinators started with `render --workers`.
+    Worker {
+        /// Address to listen on. Use 0.0.0.0 to accept other machines, but
+        /// only where just trusted coordinators can reach the port: there is
+        /// no authentication.
+        #[arg(long, default_value = "127.0.0.1:7878")]
+        listen: String,
+        /// Only render scenes whose meshes and images are inside this
+        /// directory.
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        scene_root: PathBuf,
+        /// Number of worker threads (defaults to one per core).
+        #[arg(long, short = 'j')]
+        threads: Option<usize>,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+enum DebugView {
+    /// Surface normals.
+    Normal,
+    /// Distance to the camera, white at the far end of the scene.
+    Depth,
+    /// Surface colour without lighting.
+    Albedo,
+    /// Front faces green, back faces red.
+    Facing,
+    /// One colour per object.
+    ObjectId,
+}
+
+impl DebugView {
+    fn integrator(self, camera: &Camera, bounds: &Aabb) -> DebugIntegrator {
+        let mode = match self {
+            DebugView::Normal => DebugMode::Normal,
+            DebugView::Depth => DebugMode::Depth {
+                max_distance: farthest_distance(camera.center(), bounds),
+            },
+            DebugView::Albedo => DebugMode::Albedo,
+            DebugView::Facing => DebugMode::Facing,
+            DebugView::ObjectId => DebugMode::ObjectId,
+        };
+        DebugIntegrator::new(mode)
+    }
+}
+
+fn parse_aovs(name: &str) -> Result<Vec<Aov>, String> {
+    if name == "all" {
+        return Ok(Aov::ALL.to_vec());
+    }
+    Aov::ALL
+        .into_iter()
+        .find(|aov| aov.name() == name.replace('-', "_"))
+        .map(|aov| vec![aov])
+        .ok_or_else(|| format!("unknown AOV '{}'", name))
+}
+
+fn parse_filter(name: &str) -> Result<FilterKind, String> {
+    FilterKind::ALL
+        .into_iter()
+        .find(|filter| filter.name() == name)
+        .ok_or_else(|| format!("unknown filter '{}'", name))
+}
+
+fn parse_filter_radius(value: &str) -> Result<f64, String> {
+    let radius = value.parse().map_err(|error| format!("{}", error))?;
+    check_radius(radius)
+}
+
+/// A duration given in seconds, such as `2.5`.
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let seconds: f64 = value.parse().map_err(|error| format!("{}", error))?;
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| format!("expected a positive number of seconds, not {}", value))
+}
+
+/// Like `parse_seconds`, but not zero, which sockets take to mean no
+/// timeout at all.
+fn parse_timeout(value: &str) -> Result<Duration, String> {
+    match parse_seconds(value)? {
+        Duration::ZERO => Err("a timeout of zero seconds would never end".to_string()),
+        timeout => Ok(timeout),
+    }
+}
+
+fn parse_sampler(name: &str) -> Result<SamplerKind, String> {
+    SamplerKind::ALL
+        .into_iter()
+        .find(|sampler| sampler.name() == name)
+        .ok_or_else(|| format!("unknown sampler '{}'", name))
+}
+
+fn parse_tone_curve(name: &str) -> Result<ToneCurve, String> {
+    ToneCurve::ALL
+        .into_iter()
+        .find(|curve| curve.name() == name.replace('-', "_"))
+        .ok_or_else(|| format!("unknown tone curve '{}'", name))
+}
+
+/// Distance from `point` to the farthest corner of `bounds`.
+fn farthest_distance(point: DVec3, bounds: &Aabb) -> f64 {
+    (0..8)
+        .map(|corner| {
+            let pick = |bit: usize, axis: usize| {
+                if corner & bit == 0 {
+                    bounds.min[axis]
+                } else {
+                    bounds.max[axis]
+                }
+            };
+            DVec3::new(pick(1, 0), pick(2, 1), pick(4, 2)).distance(point)
+        })
+        .fold(0.0, f64::max)
+}
+
+/// Overrides for the camera settings of a scene. Anything left unset keeps
+/// the value from the scene, or the `CameraBuilder` default.
+#[derive(Args, Debug, Default)]
+struct RenderOptions {
+    /// Image width in pixels; the height follows from the aspect ratio.
+    #[arg(long)]
+    width: Option<u32>,
+    /// Samples per pixel.
+    #[arg(long, short = 's')]
+    samples: Option<u32>,
+    /// Turn on adaptive sampling: pixels stop once their estimated error
+    /// falls below this threshold (try 0.01).
+    #[arg(long)]
+    adaptive: Option<f64>,
+    /// With adaptive sampling, samples every pixel takes at least.
+    #[arg(long)]
+    min_samples: Option<u32>,
+    /// With adaptive sampling, samples no pixel goes beyond (defaults to
+    /// --samples).
+    #[arg(long)]
+    max_samples: Option<u32>,
+    /// Maximum number of bounces per path.
+    #[arg(long)]
+    max_depth: Option<u32>,
+    /// Seed for the random numbers used while rendering.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// How samples are spread over each pixel: independent, stratified,
+    /// halton or sobol.
+    #[arg(long, value_parser = parse_sampler)]
+    sampler: Option<SamplerKind>,
+    /// Reconstruction filter: box, tent, gaussian, mitchell or lanczos.
+    #[arg(long, value_parser = parse_filter)]
+    filter: Option<FilterKind>,
+    /// Filter radius in pixels, at most 16.
+    #[arg(long, value_parser = parse_filter_radius)]
+    filter_radius: Option<f64>,
+    /// Number of worker threads (defaults to one per core).
+    #[arg(long, short = 'j')]
+    threads: Option<usize>,
+    /// Where to write the image. The extension picks the format.
+    #[arg(long, short = 'o')]
+    output: Option<PathBuf>,
+    #[command(flatten)]
+    tone_map: ToneMapArgs,
+}
+
+/// Overrides for the display transform of 8 and 16 bit output.
+#[derive(Args, Debug, Default)]
+struct ToneMapArgs {
+    /// Exposure adjustment in stops for 8 and 16 bit output.
+    #[arg(long, allow_negative_numbers = true)]
+    exposure: Option<f64>,
+    /// Tone curve for 8 and 16 bit output: clamp, reinhard,
+    /// extended-reinhard, aces or agx.
+    #[arg(long, value_parser = parse_tone_curve)]
+    tone_curve: Option<ToneCurve>,
+    /// Dither 8 and 16 bit output to hide banding.
+    #[arg(long)]
+    dither: bool,
+}
+
+impl ToneMapArgs {
+    fn apply(&self, mut tone_map: ToneMap) -> ToneMap {
+        if let Some(exposure) = self.exposure {
+            tone_map.exposure = exposure;
+        }
+        if let Some(curve) = self.tone_curve {
+            tone_map.curve = curve;
+        }
+        tone_map.dither |= self.dither;
+        tone_map
+    }
+}
+
+impl RenderOptions {
+    fn apply(&self, mut camera: CameraBuilder) -> CameraBuilder {
+        if let Some(width) = self.width {
+            camera = camera.image_width(width);
+        }
+        if let Some(samples) = self.samples {
+            camera = camera.samples_per_pixel(samples);
+        }
+        if let Some(threshold) = self.adaptive {
+            camera = camera.adaptive_threshold(threshold);
+        }
+        if let Some(min_samples) = self.min_samples {
+            camera = camera.min_samples(min_samples);
+        }
+        if let Some(max_samples) = self.max_samples {
+            camera = camera.max_samples(max_samples);
+        }
+        if let Some(max_depth) = self.max_depth {
+            camera = camera.max_depth(max_depth);
+        }
+        if let Some(seed) = self.seed {
+            camera = camera.seed(seed);
+        }
+        if let Some(sampler) = self.sampler {
+            camera = camera.sampler(sampler);
+        }
+        if let Some(filter) = self.filter {
+            camera = camera.filter(filter);
+        }
+        if let Some(filter_radius) = self.filter_radius {
+            camera = camera.filter_radius(filter_radius);
+        }
+
+        let tone_map = self.tone_map.apply(camera.tone_map.unwrap_or_default());
+        camera.tone_map(tone_map)
+    }
+
+    fn init_threads(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(threads) = self.threads {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build_global()?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings for rendering in passes and writing the image as it improves.
+#[derive(Args, Debug, Default)]
+#[command(group(ArgGroup::new("passes").multiple(true)))]
+struct ProgressiveArgs {
+    /// Render in passes of this many samples per pixel, writing the image
+    /// so far after each.
+    #[arg(long, value_name = "SAMPLES", group = "passes")]
+    progressive: Option<u32>,
+    /// Write the image and checkpoint at most every this many seconds
+    /// instead of after every pass.
+    #[arg(long, value_name = "SECONDS", requires = "passes", value_parser = parse_seconds)]
+    preview_interval: Option<Duration>,
+    /// Keep sampling all pixels until this many seconds have passed,
+    /// instead of stopping at --samples.
+    #[arg(long, value_name = "SECONDS", group = "passes", value_parser = parse_seconds)]
+    time_budget: Option<Duration>,
+    /// With --progressive or --time-budget, save a checkpoint to resume
+    /// from every minute (or --preview-interval) and at the end.
+    #[arg(long, value_name = "PATH", requires = "passes")]
+    checkpoint: Option<PathBuf>,
+    /// Carry on from a checkpoint. The scene and camera settings must be
+    /// the same, except that --samples may be raised.
+    #[arg(long, value_name = "PATH")]
+    resume: Option<PathBuf>,
+    /// Also save the accumulated samples as a partial render, to combine
+    /// with renders using other seeds with `merge`.
+    #[arg(long, value_name = "PATH")]
+    partial: Option<PathBuf>,
+}
+
+impl ProgressiveArgs {
+    /// How to render in passes, or `None` for a plain render.
+    fn options(&self) -> Option<ProgressiveOptions> {
+        if self.progressive.is_none()
+            && self.time_budget.is_none()
+            && self.resume.is_none()
+            && self.partial.is_none()
+        {
+            return None;
+        }
+        // without --progressive, a time budget samples one pass at a time
+        // and anything else renders in one go
+        let pass_samples = match (self.progressive, self.time_budget) {
+            (Some(samples), _) => samples,
+            (None, Some(_)) => 1,
+            (None, None) => u32::MAX,
+        };
+        Some(ProgressiveOptions {
+            pass_samples,
+            preview_interval: self.preview_interval,
+            time_budget: self.time_budget,
+        })
+    }
+}
+
+/// Settings for rendering on other machines.
+#[derive(Args, Debug, Default)]
+struct DistributedArgs {
+    /// Comma separated addresses of `aurora worker`s to render on. Relative
+    /// paths in the scene must resolve to the same files on every worker.
+    #[arg(
+        long,
+        value_name = "HOST:PORT",
+        value_delimiter = ',',
+        conflicts_with_all = ["debug", "progressive", "time_budget", "checkpoint", "resume"]
+    )]
+    workers: Vec<String>,
+    /// Samples per pixel handed to a worker at a time (defaults to a
+    /// quarter of each worker's share).
+    #[arg(long, value_name = "SAMPLES", requires = "workers")]
+    job_samples: Option<u32>,
+    /// Give up on a worker that takes longer than this many seconds over
+    /// one job, and hand its samples to the others.
+    #[arg(long, value_name = "SECONDS", requires = "workers", value_parser = parse_timeout)]
+    job_timeout: Option<Duration>,
+}
+
+impl DistributedArgs {
+    fn options(&self, samples_per_pixel: u32) -> DistributedOptions {
+        let default = samples_per_pixel / (4 * self.workers.len() as u32).max(1);
+        DistributedOptions {
+            job_samples: self.job_samples.unwrap_or(default).max(1),
+            job_timeout: self.job_timeout,
+        }
+    }
+}
 
-fn big_scene() -> io::Result<()> {
+/// The final scene of "Ray Tracing in One Weekend", used by `bench`.
+fn big_scene() -> (HittableList, CameraBuilder) {
     let ground_material = Material::Lambertian {
-        albedo: DVec3::new(0.5, 0.5, 0.5),
+        albedo: Texture::Solid(DVec3::new(0.5, 0.5, 0.5)),
     };
     let mut world = HittableList { objects: vec![] };
     world.objects.push(Box::new(Sphere {
@@ -17,7 +417,8 @@ fn big_scene() -> io::Result<()> {
         radius: 1000.0,
         material: ground_material,
     }));
-    let mut rng = rand::thread_rng();
+    // fixed seed so the scene is laid out the same way on every run
+    let mut rng = SmallRng::seed_from_u64(0);
 
     for a in -11..11 {
         for b in -11..11 {
@@ -35,7 +436,9 @@ fn big_scene() -> io::Result<()> {
 
                 if choose_mat < 0.8 {
                     let albedo = random_color_1 * random_color_2;
-                    sphere_mat = Material::Lambertian { albedo };
+                    sphere_mat = Material::Lambertian {
+                        albedo: Texture::Solid(albedo),
+                    };
                     world.objects.push(Box::new(Sphere {
                         center,
                         radius: 0.2,
@@ -48,7 +451,10 @@ fn big_scene() -> io::Result<()> {
                         rng.gen_range(0.5..1.0),
                     );
                     let fuzz = rng.gen_range(0.0..0.5);
-                    sphere_mat = Material::Metal { albedo, fuzz };
+                    sphere_mat = Material::Metal {
+                        albedo: Texture::Solid(albedo),
+                        fuzz,
+                    };
                     world.objects.push(Box::new(Sphere {
                         center,
                         radius: 0.2,
@@ -78,7 +484,7 @@ fn big_scene() -> io::Result<()> {
     }));
 
     let material2 = Material::Lambertian {
-        albedo: DVec3::new(0.4, 0.2, 0.1),
+        albedo: Texture::Solid(DVec3::new(0.4, 0.2, 0.1)),
     };
     world.objects.push(Box::new(Sphere {
         center: DVec3::new(-4.0, 1.0, 0.),
@@ -87,7 +493,7 @@ fn big_scene() -> io::Result<()> {
     }));
 
     let material3 = Material::Metal {
-        albedo: DVec3::new(0.7, 0.6, 0.5),
+        albedo: Texture::Solid(DVec3::new(0.7, 0.6, 0.5)),
         fuzz: 0.0,
     };
     world.objects.push(Box::new(Sphere {
@@ -96,7 +502,7 @@ fn big_scene() -> io::Result<()> {
         material: material3,
     }));
 
-    let mut camera = CameraBuilder::new()
+    let camera = CameraBuilder::new()
         .image_width(600)
         .aspect_ratio(16.0 / 9.0)
         .samples_per_pixel(200)
@@ -106,56 +512,480 @@ fn big_scene() -> io::Result<()> {
         .look_at(DVec3::new(0., 0., 0.))
         .defocus_angle(0.6)
         .focus_dist(10.)
-        .v_up(DVec3::Y)
+        .v_up(DVec3::Y);
+
+    (world, camera)
+}
+
+/// How often `--checkpoint` saves without `--preview-interval`.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+fn render(
+    scene_path: PathBuf,
+    debug: Option<DebugView>,
+    aovs: Vec<Aov>,
+    progressive: ProgressiveArgs,
+    distributed: DistributedArgs,
+    options: RenderOptions,
+) -> Result<(), Box<dyn Error>> {
+    options.init_threads()?;
+    let scene = load_scene(&scene_path)?;
+    let mut builder = options.apply(scene.camera);
+    if !aovs.is_empty() {
+        builder = builder.aovs(aovs);
+    }
+    if !distributed.workers.is_empty() {
+        let output = options.output.unwrap_or(scene.render.output);
+        return render_on_workers(&scene_path, builder, &distributed, &progressive, &output);
+    }
+    let mut camera = builder.clone().build();
+    if let Some(debug) = debug {
+        let integrator = debug.integrator(&camera, &scene.world.bounding_box());
+        camera = builder.integrator(integrator).build();
+    }
+    let output = options.output.unwrap_or(scene.render.output);
+
+    let world = BvhNode::new(scene.world);
+    if let Some(options) = progressive.options() {
+        let accumulator = match &progressive.resume {
+            Some(path) => load_checkpoint(path, scene.fingerprint, &camera)?,
+            None => camera.accumulator(),
+        };
+        let tone_map = camera.tone_map();
+        let save_checkpoint = |accumulator: &Accumulator| match &progressive.checkpoint {
+            Some(path) => save_checkpoint(path, scene.fingerprint, &camera, accumulator),
+            None => Ok(()),
+        };
+        let checkpoint_interval = options.preview_interval.unwrap_or(CHECKPOINT_INTERVAL);
+        let mut last_checkpoint = Instant::now();
+        let accumulator = camera.resume_progressive(
+            &world,
+            &scene.lights,
+            accumulator,
+            &options,
+            |accumulator| {
+                if progressive.progressive.is_some() {
+                    accumulator.output().save(&output, tone_map)?;
+                }
+                // passes can be quick, and saving the whole accumulator
+                // after each would take more time than the passes
+                if last_checkpoint.elapsed() >= checkpoint_interval {
+                    save_checkpoint(accumulator)?;
+                    last_checkpoint = Instant::now();
+                }
+                Ok(())
+            },
+        )?;
+        accumulator.output().save(&output, tone_map)?;
+        save_checkpoint(&accumulator)?;
+        if let Some(path) = &progressive.partial {
+            let samples = 0..accumulator.samples_end();
+            PartialRender::new(scene.fingerprint, &camera, samples, accumulator).save(path)?;
+            println!("Wrote {}", path.display());
+        }
+    } else {
+        camera.render(&world, &scene.lights, &output)?;
+    }
+    println!("Wrote {}", output.display());
+    Ok(())
+}
+
+fn render_on_workers(
+    scene_path: &Path,
+    camera: CameraBuilder,
+    distributed: &DistributedArgs,
+    progressive: &ProgressiveArgs,
+    output: &Path,
+) -> Result<(), Box<dyn Error>> {
+    // workers resolve relative paths against the same directory
+    let base_dir = fs::canonicalize(scene_path)?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let scene = DistributedScene {
+        source: fs::read_to_string(scene_path)?,
+        base_dir,
+        camera: camera.clone(),
+    };
+    let mut workers = vec![];
+    for worker in &distributed.workers {
+        let addresses = worker
+            .to_socket_addrs()
+            .map_err(|error| format!("{}: {}", worker, error))?;
+        workers.extend(addresses.take(1));
+    }
+    let camera = camera.build();
+    let options = distributed.options(camera.samples_per_pixel());
+
+    let merged = render_distributed(&scene, &workers, &options)?;
+    merged
+        .accumulator
+        .output()
+        .save(output, camera.tone_map())?;
+    if let Some(path) = &progressive.partial {
+        merged.save(path)?;
+        println!("Wrote {}", path.display());
+    }
+    println!("Wrote {}", output.display());
+    Ok(())
+}
+
+fn worker(
+    listen: String,
+    scene_root: PathBuf,
+    threads: Option<usize>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(threads) = threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
+    let listener = TcpListener::bind(&listen)?;
+    println!("Listening on {}", listener.local_addr()?);
+    serve(listener, scene_root)?;
+    Ok(())
+}
+
+fn info(scene_path: PathBuf, options: RenderOptions) -> Result<(), Box<dyn Error>> {
+    let scene = load_scene(&scene_path)?;
+    let camera = options.apply(scene.camera).build();
+    let output = options.output.unwrap_or(scene.render.output);
+    let bounds = scene.world.bounding_box();
+
+    println!("scene:      {}", scene_path.display());
+    println!("output:     {}", output.display());
+    println!(
+        "resolution: {}x{}",
+        camera.image_width(),
+        camera.image_height()
+    );
+    println!("samples:    {}", camera.samples_per_pixel());
+    println!("max depth:  {}", camera.max_depth());
+    println!("seed:       {}", camera.seed());
+    println!("objects:    {}", scene.world.objects.len());
+    println!("lights:     {}", scene.lights.objects.len());
+    println!("bounds:     {} to {}", bounds.min, bounds.max);
+    Ok(())
+}
+
+/// Samples per pixel for `bench` when `--samples` isn't given, low enough
+/// for a quick run.
+const BENCH_SAMPLES: u32 = 16;
+
+fn bench(options: RenderOptions) -> Result<(), Box<dyn Error>> {
+    options.init_threads()?;
+    let (world, camera) = big_scene();
+    let camera = options
+        .apply(camera.samples_per_pixel(BENCH_SAMPLES))
         .build();
 
-    let _ = camera.render(&world, "spheres-big-scene.ppm".to_string());
+    let start = Instant::now();
+    let world = BvhNode::new(world);
+    let bvh_time = start.elapsed();
+    let framebuffer = camera.render_to_buffer(&world, &HittableList::default());
+    let render_time = start.elapsed() - bvh_time;
+
+    let samples = framebuffer.pixels().len() as f64 * camera.samples_per_pixel() as f64;
+    println!("threads:     {}", rayon::current_num_threads());
+    println!(
+        "resolution:  {}x{} at {} spp",
+        camera.image_width(),
+        camera.image_height(),
+        camera.samples_per_pixel()
+    );
+    println!("bvh build:   {:?}", bvh_time);
+    println!("render:      {:?}", render_time);
+    println!(
+        "throughput:  {:.2} Msamples/s",
+        samples / render_time.as_secs_f64() / 1e6
+    );
+
+    if let Some(output) = options.output {
+        framebuffer.save(&output, camera.tone_map())?;
+        println!("Wrote {}", output.display());
+    }
     Ok(())
 }
 
-// fn simple() -> io::Result<()> {
-//     let ground_material = Material::Lambertian {
-//         albedo: DVec3::new(0.5, 0.5, 0.5),
-//     };
-//     let mut world = HittableList { objects: vec![] };
-
-//     let material2 = Material::Lambertian {
-//         albedo: DVec3::new(0.5, 0.5, 0.5),
-//     };
-//     world.objects.push(Box::new(Sphere {
-//         center: DVec3::new(0., 0., -1.2),
-//         radius: 0.5,
-//         material: material2,
-//     }));
-//     world.objects.push(Box::new(Sphere {
-//         center: DVec3::new(0.0, -100.5, -1.0),
-//         radius: 100.0,
-//         material: ground_material,
-//     }));
-
-//     let aspect_ratio = 16.0 / 9.0;
-//     let image_width = 600;
-
-//     let mut camera = CameraBuilder::new()
-//         .image_width(image_width)
-//         .aspect_ratio(aspect_ratio)
-//         .samples_per_pixel(100)
-//         .max_depth(20)
-//         .vertical_fov(20.)
-//         .look_from(DVec3::new(13., 2., 3.))
-//         .look_at(DVec3::new(0., 0., 0.))
-//         .v_up(DVec3::new(0., 1., 0.))
-//         // .defocus_angle(60.)
-//         .focus_dist(10.)
-//         .build();
-
-//     let _ = camera.render(&world, "spheres-simple-scene.ppm".to_string());
-//     println!("Rendered ok");
-//     Ok(())
-// }
-
-fn main() -> io::Result<()> {
-    big_scene()?;
+fn merge(
+    partials: Vec<PathBuf>,
+    output: PathBuf,
+    tone_map: ToneMapArgs,
+) -> Result<(), Box<dyn Error>> {
+    let mut merged: Option<PartialRender> = None;
+    for path in &partials {
+        let in_file = |error| format!("{}: {}", path.display(), error);
+        let partial = PartialRender::load(path).map_err(in_file)?;
+        match &mut merged {
+            Some(merged) => merged.merge(&partial).map_err(in_file)?,
+            None => merged = Some(partial),
+        }
+    }
+    let merged = merged.expect("clap requires at least one partial render");
 
+    if output
+        .extension()
+        .is_some_and(|extension| extension == "partial")
+    {
+        merged.save(&output)?;
+    } else {
+        let tone_map = tone_map.apply(ToneMap::default());
+        merged.accumulator.output().save(&output, &tone_map)?;
+    }
+    println!(
+        "Merged {} partial renders into {}",
+        partials.len(),
+        output.display()
+    );
     Ok(())
 }
+
+fn main() -> Result<(), Box<dyn Error>> {
+    match Cli::parse().command {
+        Command::Render {
+            scene,
+            debug,
+            aov,
+            progressive,
+            distributed,
+            options,
+        } => render(
+            scene,
+            debug,
+            aov.concat(),
+            progressive,
+            distributed,
+            options,
+        ),
+        Command::Info { scene, options } => info(scene, options),
+        Command::Bench { options } => bench(options),
+        Command::Merge {
+            partials,
+            output,
+            tone_map,
+        } => merge(partials, output, tone_map),
+        Command::Worker {
+            listen,
+            scene_root,
+            threads,
+        } => worker(listen, scene_root, threads),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_keep_scene_values() {
+        let cli =
+            Cli::try_parse_from(["aurora", "render", "scene.toml", "--samples", "4"]).unwrap();
+        let Command::Render { scene, options, .. } = cli.command else {
+            panic!("expected the render subcommand");
+        };
+        assert_eq!(scene, PathBuf::from("scene.toml"));
+
+        let camera = options.apply(CameraBuilder::new().image_width(64).samples_per_pixel(100));
+        assert_eq!(camera.image_width, Some(64));
+        assert_eq!(camera.samples_per_pixel, Some(4));
+        assert_eq!(camera.seed, None);
+        assert_eq!(camera.tone_map, Some(ToneMap::default()));
+    }
+
+    #[test]
+    fn test_tone_map_options() {
+        let cli = Cli::try_parse_from([
+            "aurora",
+            "bench",
+            "--exposure",
+            "-1.5",
+            "--tone-curve",
+            "extended-reinhard",
+            "--sampler",
+            "sobol",
+            "--filter",
+            "mitchell",
+            "--adaptive",
+            "0.02",
+            "--max-samples",
+            "512",
+        ])
+        .unwrap();
+        let Command::Bench { options } = cli.command else {
+            panic!("expected the bench subcommand");
+        };
+        let scene_tone_map = ToneMap {
+            curve: ToneCurve::Aces,
+            dither: true,
+            ..ToneMap::default()
+        };
+        let camera = options.apply(CameraBuilder::new().tone_map(scene_tone_map));
+        let tone_map = camera.tone_map.unwrap();
+        assert_eq!(tone_map.exposure, -1.5);
+        assert_eq!(tone_map.curve, ToneCurve::ExtendedReinhard);
+        assert!(tone_map.dither);
+        assert_eq!(camera.sampler, Some(SamplerKind::Sobol));
+        assert_eq!(camera.filter, Some(FilterKind::Mitchell));
+        assert_eq!(camera.adaptive_threshold, Some(0.02));
+        assert_eq!(camera.max_samples, Some(512));
+        assert!(Cli::try_parse_from(["aurora", "bench", "--tone-curve", "nope"]).is_err());
+        assert!(Cli::try_parse_from(["aurora", "bench", "--filter-radius", "0"]).is_err());
+        assert!(Cli::try_parse_from(["aurora", "bench", "--filter-radius", "5e9"]).is_err());
+    }
+
+    #[test]
+    fn test_debug_view() {
+        let cli =
+            Cli::try_parse_from(["aurora", "render", "a.toml", "--debug", "object-id"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Render {
+                debug: Some(DebugView::ObjectId),
+                ..
+            }
+        ));
+
+        let cli = Cli::try_parse_from(["aurora", "render", "a.toml", "--aov", "depth,object-id"])
+            .unwrap();
+        let Command::Render { aov, .. } = cli.command else {
+            panic!("expected the render subcommand");
+        };
+        assert_eq!(aov.concat(), vec![Aov::Depth, Aov::ObjectId]);
+        assert!(Cli::try_parse_from(["aurora", "render", "a.toml", "--aov", "nope"]).is_err());
+
+        let bounds = Aabb::new(DVec3::ZERO, DVec3::ONE);
+        let distance = farthest_distance(DVec3::new(0., 0., -1.), &bounds);
+        assert!((distance - 6.0_f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_progressive_options() {
+        let cli = Cli::try_parse_from([
+            "aurora",
+            "render",
+            "a.toml",
+            "--progressive",
+            "8",
+            "--preview-interval",
+            "2.5",
+        ])
+        .unwrap();
+        let Command::Render { progressive, .. } = cli.command else {
+            panic!("expected the render subcommand");
+        };
+        let options = progressive.options().unwrap();
+        assert_eq!(options.pass_samples, 8);
+        assert_eq!(options.preview_interval, Some(Duration::from_millis(2500)));
+        assert_eq!(options.time_budget, None);
+
+        let cli = Cli::try_parse_from([
+            "aurora",
+            "render",
+            "a.toml",
+            "--time-budget",
+            "60",
+            "--checkpoint",
+            "a.ckpt",
+        ])
+        .unwrap();
+        let Command::Render { progressive, .. } = cli.command else {
+            panic!("expected the render subcommand");
+        };
+        let options = progressive.options().unwrap();
+        assert_eq!(options.pass_samples, 1);
+        assert_eq!(options.time_budget, Some(Duration::from_secs(60)));
+
+        assert!(ProgressiveArgs::default().options().is_none());
+        assert!(
+            Cli::try_parse_from(["aurora", "render", "a.toml", "--preview-interval", "1"]).is_err()
+        );
+        assert!(
+            Cli::try_parse_from(["aurora", "render", "a.toml", "--checkpoint", "a.ckpt"]).is_err()
+        );
+        for seconds in ["-1", "nan", "inf"] {
+            let time_budget = format!("--time-budget={}", seconds);
+            assert!(Cli::try_parse_from(["aurora", "render", "a.toml", &time_budget]).is_err());
+        }
+        let cli =
+            Cli::try_parse_from(["aurora", "render", "a.toml", "--resume", "a.ckpt"]).unwrap();
+        let Command::Render { progressive, .. } = cli.command else {
+            panic!("expected the render subcommand");
+        };
+        assert_eq!(progressive.options().unwrap().pass_samples, u32::MAX);
+    }
+
+    #[test]
+    fn test_merge_options() {
+        let cli = Cli::try_parse_from([
+            "aurora",
+            "merge",
+            "a.partial",
+            "b.partial",
+            "-o",
+            "out.png",
+            "--tone-curve",
+            "aces",
+        ])
+        .unwrap();
+        let Command::Merge {
+            partials, tone_map, ..
+        } = cli.command
+        else {
+            panic!("expected the merge subcommand");
+        };
+        assert_eq!(partials.len(), 2);
+        assert_eq!(tone_map.apply(ToneMap::default()).curve, ToneCurve::Aces);
+        assert!(Cli::try_parse_from(["aurora", "merge", "-o", "out.png"]).is_err());
+    }
+
+    #[test]
+    fn test_render_requires_scene() {
+        assert!(Cli::try_parse_from(["aurora", "render"]).is_err());
+        assert!(Cli::try_parse_from(["aurora", "bench", "-j", "2", "-o", "out.png"]).is_ok());
+    }
+
+    #[test]
+    fn test_distributed_options() {
+        let cli = Cli::try_parse_from([
+            "aurora",
+            "render",
+            "a.toml",
+            "--workers",
+            "10.0.0.1:7878,10.0.0.2:7878",
+            "--job-timeout",
+            "30",
+        ])
+        .unwrap();
+        let Command::Render { distributed, .. } = cli.command else {
+            panic!("expected the render subcommand");
+        };
+        assert_eq!(distributed.workers, ["10.0.0.1:7878", "10.0.0.2:7878"]);
+        let options = distributed.options(64);
+        assert_eq!(options.job_samples, 8);
+        assert_eq!(options.job_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(distributed.options(3).job_samples, 1);
+        for timeout in ["-5", "nan", "0"] {
+            let args = ["aurora", "render", "a.toml", "--workers", "localhost:7878"];
+            let args = args.into_iter().chain(["--job-timeout", timeout]);
+            assert!(Cli::try_parse_from(args).is_err());
+        }
+
+        for conflicting in [["--debug", "normal"], ["--progressive", "4"]] {
+            let mut args = vec!["aurora", "render", "a.toml", "--workers", "localhost:7878"];
+            args.extend(conflicting);
+            assert!(Cli::try_parse_from(args).is_err());
+        }
+        assert!(Cli::try_parse_from(["aurora", "render", "a.toml", "--job-samples", "4"]).is_err());
+        let cli = Cli::try_parse_from(["aurora", "worker"]).unwrap();
+        let Command::Worker {
+            listen, scene_root, ..
+        } = cli.command
+        else {
+            panic!("expected the worker subcommand");
+        };
+        assert_eq!(
+            (listen.as_str(), scene_root),
+            ("127.0.0.1:7878", PathBuf::from("."))
+        );
+    }
+}
@@ -1,54 +1,82 @@
-use std::ops::Neg;
+use std::{f64::consts::PI, ops::Neg};
 
 use glam::DVec3;
-use rand::Rng;
 
-use crate::{hittable::HitRecord, ray::Ray};
+use crate::{
+    fastrand::{hash_values, hash_vec, random_f64, random_in_range},
+    hittable::HitRecord,
+    ray::Ray,
+    texture::Texture,
+};
 
 /// Note - albedo is how much light is reflected.
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub enum Material {
     ///   Diffuse reflectance. Can be implemented by either always scatter
-    ///   and attenuating light according to reflectance R, or it can 
+    ///   and attenuating light according to reflectance R, or it can
     ///   sometimes scatter with probability 1 - R with no attenuation,
-    ///   and absorb any ray that isn't scattered. Or some combination. 
-    /// 
+    ///   and absorb any ray that isn't scattered. Or some combination.
+    ///
     ///   This implementation always scatters.
-    Lambertian { albedo: DVec3 },
+    Lambertian { albedo: Texture },
     ///   Reflective material.
     ///   
-    Metal { albedo: DVec3, fuzz: f64 },
+    Metal { albedo: Texture, fuzz: f64 },
     ///   Any clear material.
     Dielectric { refractive_index: f64 },
+    ///   Area light. Emits `emit` from its front face and absorbs
+    ///   everything that hits it.
+    DiffuseLight { emit: DVec3 },
+}
+
+/// The outcome of a ray scattering off a surface.
+pub struct ScatterRecord {
+    /// BSDF times cosine divided by the pdf, i.e. the path throughput weight.
+    pub attenuation: DVec3,
+    pub ray: Ray,
+    /// Solid angle density `ray` was sampled with, or `None` for specular
+    /// directions that light sampling can never produce.
+    pub pdf: Option<f64>,
 }
 
 impl Material {
-    pub fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(DVec3, Ray)> {
+    pub fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
         match self {
             Material::Lambertian { albedo } => {
                 let mut scatter_direction = hit_record.outward_normal + random_unit_vector();
-                
+
                 // avoid where result of scatter_direction is close to 0 to prevent infinites/NaNs
                 if near_zero(&scatter_direction) {
                     scatter_direction = hit_record.outward_normal;
                 }
 
+                // normal + unit vector is cosine distributed, so the cosine
+                // and the pdf cancel out of the attenuation
                 let scattered = Ray::new(hit_record.point, scatter_direction);
+                let pdf = self.scattering_pdf(hit_record, scatter_direction);
 
-                Some((*albedo, scattered))
+                Some(ScatterRecord {
+                    attenuation: albedo.value(hit_record.u, hit_record.v, hit_record.point),
+                    ray: scattered,
+                    pdf: Some(pdf),
+                })
             }
             Material::Metal { albedo, fuzz } => {
                 let mut reflected = reflect(&ray.direction, &hit_record.outward_normal);
                 reflected = reflected.normalize() + (fuzz * random_unit_vector());
                 let scattered = Ray::new(hit_record.point, reflected);
                 if scattered.direction.dot(hit_record.outward_normal) > 0.0 {
-                    return Some((*albedo, scattered));
+                    let attenuation = albedo.value(hit_record.u, hit_record.v, hit_record.point);
+                    return Some(ScatterRecord {
+                        attenuation,
+                        ray: scattered,
+                        pdf: None,
+                    });
                 }
                 None
             }
             Material::Dielectric { refractive_index } => {
-                let mut rng = rand::thread_rng();
                 let attenuation = DVec3::new(1.0, 1.0, 1.0);
                 let ri = if hit_record.front_face {
                     1.0 / refractive_index
@@ -61,18 +89,82 @@ impl Material {
                 let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
                 let cannot_refract = ri * sin_theta > 1.0;
-                let direction: DVec3;
-
-                if cannot_refract || reflectance(cos_theta, ri) > rng.gen::<f64>() {
-                    direction = reflect(&unit_direction, &hit_record.outward_normal);
+                let direction = if cannot_refract || reflectance(cos_theta, ri) > random_f64() {
+                    reflect(&unit_direction, &hit_record.outward_normal)
                 } else {
-                    direction = refract(&unit_direction, &hit_record.outward_normal, ri);
-                }
+                    refract(&unit_direction, &hit_record.outward_normal, ri)
+                };
 
                 let scattered = Ray::new(hit_record.point, direction);
 
-                Some((attenuation, scattered))
+                Some(ScatterRecord {
+                    attenuation,
+                    ray: scattered,
+                    pdf: None,
+                })
+            }
+            Material::DiffuseLight { .. } => None,
+        }
+    }
+
+    /// BSDF times cosine for light arriving from `direction`. Zero for
+    /// specular materials, whose lobes are infinitely thin.
+    pub fn eval(&self, hit_record: &HitRecord, direction: DVec3) -> DVec3 {
+        match self {
+            Material::Lambertian { albedo } => {
+                let cosine = hit_record.outward_normal.dot(direction.normalize());
+                if cosine <= 0.0 {
+                    return DVec3::ZERO;
+                }
+                albedo.value(hit_record.u, hit_record.v, hit_record.point) * cosine / PI
+            }
+            _ => DVec3::ZERO,
+        }
+    }
+
+    /// Density with which `scatter` picks `direction`, per unit solid angle.
+    pub fn scattering_pdf(&self, hit_record: &HitRecord, direction: DVec3) -> f64 {
+        match self {
+            Material::Lambertian { .. } => {
+                let cosine = hit_record.outward_normal.dot(direction.normalize());
+                cosine.max(0.0) / PI
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// The surface colour at the hit point, ignoring lighting. Clear
+    /// materials are white and lights their emission scaled into [0, 1].
+    pub fn albedo(&self, hit_record: &HitRecord) -> DVec3 {
+        match self {
+            Material::Lambertian { albedo } | Material::Metal { albedo, .. } => {
+                albedo.value(hit_record.u, hit_record.v, hit_record.point)
+            }
+            Material::Dielectric { .. } => DVec3::ONE,
+            Material::DiffuseLight { emit } => *emit / emit.max_element().max(1.0),
+        }
+    }
+
+    /// A hash of the material's parameters, so identical materials on
+    /// different objects can be told apart from different ones.
+    pub fn fingerprint(&self) -> u64 {
+        match self {
+            Material::Lambertian { albedo } => hash_values(1, &[albedo.fingerprint()]),
+            Material::Metal { albedo, fuzz } => {
+                hash_values(2, &[albedo.fingerprint(), fuzz.to_bits()])
             }
+            Material::Dielectric { refractive_index } => {
+                hash_values(3, &[refractive_index.to_bits()])
+            }
+            Material::DiffuseLight { emit } => hash_vec(4, *emit),
+        }
+    }
+
+    /// Light given off by the surface at the hit point, before any scattering.
+    pub fn emitted(&self, hit_record: &HitRecord) -> DVec3 {
+        match self {
+            Material::DiffuseLight { emit } if hit_record.front_face => *emit,
+            _ => DVec3::ZERO,
         }
     }
 }
@@ -94,32 +186,22 @@ fn refract(uv: &DVec3, n: &DVec3, etai_over_etat: f64) -> DVec3 {
     r_out_perp + r_out_parallel
 }
 
-/// Schlick's approximation for reflectance based on 
-/// the cosine of 
+/// Schlick's approximation for reflectance based on
+/// the cosine of
 fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
     let mut r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
     r0 = r0 * r0;
     r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
 }
 
-/// Calculates a random vector on the unit sphere and normalizes it
-/// to extend to the sphere surface.
-/// Avoids cases where very small vector components round to 0 when squared
-/// by rejection points that lie within a "black hole" around the center.
-/// For f64, support values of 1e-160 and greater.
+/// A uniformly distributed point on the unit sphere.
+/// Maps exactly two random numbers, rather than rejecting points outside
+/// the sphere, so samplers see the same dimensions on every bounce.
 fn random_unit_vector() -> DVec3 {
-    let mut rng = rand::thread_rng();
-
-    loop {
-        let x = rng.gen_range(-1.0..1.0);
-        let y = rng.gen_range(-1.0..1.0);
-        let z = rng.gen_range(-1.0..1.0);
-        let v = DVec3::new(x, y, z);
-        let len_sq = v.length_squared();
-        if len_sq > 1e-160 && len_sq <= 1.0 {
-            return v / len_sq.sqrt();
-        }
-    }
+    let z = random_in_range(-1.0, 1.0);
+    let phi = 2.0 * PI * random_f64();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    DVec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
 #[cfg(test)]
@@ -130,8 +212,65 @@ mod tests {
     #[test]
     fn test_scatter_lambertian() {
         let lambertian = Material::Lambertian {
-            albedo: DVec3::new(1., 2., 1.),
+            albedo: Texture::Solid(DVec3::new(1., 2., 1.)),
         };
+        let ray = Ray::new(DVec3::new(0., 0., 2.), DVec3::new(0., 0., -1.));
+        let hit_record = HitRecord::new(DVec3::new(0., 0., 1.), DVec3::Z, 1., &ray, &lambertian);
+
+        let scattered = lambertian.scatter(&ray, &hit_record).unwrap();
+        assert_eq!(scattered.attenuation, DVec3::new(1., 2., 1.));
+        assert_eq!(scattered.ray.origin, hit_record.point);
+        assert!(scattered.ray.direction.dot(hit_record.outward_normal) >= 0.);
+        assert!(scattered.pdf.unwrap() > 0.);
+    }
+
+    #[test]
+    fn test_lambertian_eval_matches_pdf() {
+        let lambertian = Material::Lambertian {
+            albedo: Texture::Solid(DVec3::splat(0.5)),
+        };
+        let ray = Ray::new(DVec3::new(0., 0., 2.), DVec3::new(0., 0., -1.));
+        let hit_record = HitRecord::new(DVec3::ZERO, DVec3::Z, 2., &ray, &lambertian);
+
+        // for a cosine distributed lambertian, eval / pdf is the albedo
+        let direction = DVec3::new(1., 0., 1.);
+        let eval = lambertian.eval(&hit_record, direction);
+        let pdf = lambertian.scattering_pdf(&hit_record, direction);
+        assert!((eval / pdf - DVec3::splat(0.5)).length() < 1e-12);
+        assert_eq!(lambertian.eval(&hit_record, -DVec3::Z), DVec3::ZERO);
+
+        let glass = Material::Dielectric {
+            refractive_index: 1.5,
+        };
+        assert!(glass.scatter(&ray, &hit_record).unwrap().pdf.is_none());
+    }
+
+    #[test]
+    fn test_diffuse_light_emits_from_front_face() {
+        let light = Material::DiffuseLight {
+            emit: DVec3::new(4., 4., 4.),
+        };
+        let front = Ray::new(DVec3::new(0., 0., 2.), DVec3::new(0., 0., -1.));
+        let back = Ray::new(DVec3::new(0., 0., -2.), DVec3::new(0., 0., 1.));
+        let front_hit = HitRecord::new(DVec3::ZERO, DVec3::Z, 2., &front, &light);
+        let back_hit = HitRecord::new(DVec3::ZERO, DVec3::Z, 2., &back, &light);
+
+        assert_eq!(light.emitted(&front_hit), DVec3::new(4., 4., 4.));
+        assert_eq!(light.emitted(&back_hit), DVec3::ZERO);
+        assert!(light.scatter(&front, &front_hit).is_none());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let grey = || Material::Lambertian {
+            albedo: Texture::Solid(DVec3::splat(0.5)),
+        };
+        let metal = Material::Metal {
+            albedo: Texture::Solid(DVec3::splat(0.5)),
+            fuzz: 0.0,
+        };
+        assert_eq!(grey().fingerprint(), grey().fingerprint());
+        assert_ne!(grey().fingerprint(), metal.fingerprint());
     }
 
     #[test]
@@ -139,13 +278,11 @@ mod tests {
         let random_vec1 = random_unit_vector();
         let random_vec2 = random_unit_vector();
         let random_vec3 = random_unit_vector();
-        assert_eq!(random_vec1.length(), 1.);
-        assert_eq!(random_vec2.length(), 1.);
-        assert_eq!(random_vec3.length(), 1.);
+        assert!((random_vec1.length() - 1.).abs() < 1e-12);
+        assert!((random_vec2.length() - 1.).abs() < 1e-12);
+        assert!((random_vec3.length() - 1.).abs() < 1e-12);
     }
 
     #[test]
-    fn test_reflectance() {
-
-    }
+    fn test_reflectance() {}
 }
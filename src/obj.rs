@@ -0,0 +1,437 @@
+//! Wavefront OBJ/MTL loading.
+//!
+//! Supports positions, normals, texture coordinates and faces (polygons are
+//! fan triangulated), plus the `.mtl` libraries they reference. MTL
+//! materials are approximated with the closest `Material` variant.
+
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use glam::{DVec2, DVec3};
+
+use crate::{
+    material::Material,
+    shapes::mesh::{MeshData, MeshFace, MeshVertex, TriangleMesh},
+    texture::Texture,
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io { path: PathBuf, source: io::Error },
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+/// Loads an OBJ file and the material libraries it references.
+/// Faces without a material (or with one the libraries don't define) use
+/// `default_material`.
+pub fn load_obj(
+    path: impl AsRef<Path>,
+    default_material: Material,
+) -> Result<TriangleMesh, ObjError> {
+    Ok(TriangleMesh::new(load_obj_data(path, default_material)?))
+}
+
+/// Like `load_obj`, but returns the raw mesh data so it can be edited
+/// (e.g. transformed) before building the mesh.
+pub fn load_obj_data(
+    path: impl AsRef<Path>,
+    default_material: Material,
+) -> Result<MeshData, ObjError> {
+    let path = path.as_ref();
+    let source = read_file(path)?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let mut materials = HashMap::new();
+    for line in source.lines() {
+        if let Some(("mtllib", names)) = split_keyword(line) {
+            for name in names.split_whitespace() {
+                let mtl_path = directory.join(name);
+                materials.extend(parse_mtl(&read_file(&mtl_path)?)?);
+            }
+        }
+    }
+
+    parse_obj(&source, &materials, default_material)
+}
+
+/// Parses the contents of an OBJ file. `mtllib` statements are ignored, the
+/// materials they define must already be in `materials`.
+pub fn parse_obj(
+    source: &str,
+    materials: &HashMap<String, Material>,
+    default_material: Material,
+) -> Result<MeshData, ObjError> {
+    let mut data = MeshData {
+        materials: vec![default_material],
+        ..Default::default()
+    };
+    // material name -> index into data.materials
+    let mut material_indices: HashMap<&str, usize> = HashMap::new();
+    let mut current_material = 0;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let Some((keyword, rest)) = split_keyword(line) else {
+            continue;
+        };
+
+        match keyword {
+            "v" => data.positions.push(parse_dvec3(rest, line_number)?),
+            "vn" => data.normals.push(parse_dvec3(rest, line_number)?),
+            "vt" => {
+                let values = parse_floats(rest, line_number)?;
+                let (Some(&u), v) = (values.first(), values.get(1).copied()) else {
+                    return Err(parse_error(line_number, "expected at least 1 coordinate"));
+                };
+                data.texcoords.push(DVec2::new(u, v.unwrap_or(0.0)));
+            }
+            "f" => {
+                let corners = rest
+                    .split_whitespace()
+                    .map(|corner| parse_vertex(corner, &data, line_number))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return Err(parse_error(line_number, "face needs at least 3 vertices"));
+                }
+                // fan triangulation around the first corner
+                for i in 1..corners.len() - 1 {
+                    data.faces.push(MeshFace {
+                        vertices: [corners[0], corners[i], corners[i + 1]],
+                        material: current_material,
+                    });
+                }
+            }
+            "usemtl" => {
+                let name = rest.trim();
+                current_material = match materials.get(name) {
+                    Some(material) => *material_indices.entry(name).or_insert_with(|| {
+                        data.materials.push(material.clone());
+                        data.materials.len() - 1
+                    }),
+                    None => 0,
+                };
+            }
+            // groups, objects, smoothing groups and material libraries don't
+            // change the geometry
+            _ => {}
+        }
+    }
+
+    Ok(data)
+}
+
+/// Parses the contents of an MTL file into materials keyed by name.
+///
+/// * materials with an emission colour `Ke` become `DiffuseLight`
+/// * transparent materials (`d` < 1, `Tr` > 0, or a refraction `illum`
+///   model) become `Dielectric` with index of refraction `Ni`
+/// * materials with mirror reflection (`illum` 3 or 5) or whose specular
+///   colour `Ks` outweighs the diffuse `Kd` become `Metal`, with the
+///   Phong exponent `Ns` mapped to fuzz
+/// * everything else is `Lambertian` with albedo `Kd`
+pub fn parse_mtl(source: &str) -> Result<HashMap<String, Material>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlProperties)> = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let Some((keyword, rest)) = split_keyword(line) else {
+            continue;
+        };
+
+        if keyword == "newmtl" {
+            if let Some((name, properties)) = current.take() {
+                materials.insert(name, properties.to_material());
+            }
+            current = Some((rest.trim().to_string(), MtlProperties::default()));
+            continue;
+        }
+
+        let Some((_, properties)) = current.as_mut() else {
+            continue;
+        };
+        match keyword {
+            "Kd" => properties.diffuse = parse_dvec3(rest, line_number)?,
+            "Ks" => properties.specular = parse_dvec3(rest, line_number)?,
+            "Ke" => properties.emission = parse_dvec3(rest, line_number)?,
+            "Ns" => properties.shininess = parse_float(rest, line_number)?,
+            "Ni" => properties.refractive_index = parse_float(rest, line_number)?,
+            "d" => properties.dissolve = parse_float(rest, line_number)?,
+            "Tr" => properties.dissolve = 1.0 - parse_float(rest, line_number)?,
+            "illum" => properties.illumination = parse_float(rest, line_number)? as u32,
+            _ => {}
+        }
+    }
+
+    if let Some((name, properties)) = current {
+        materials.insert(name, properties.to_material());
+    }
+
+    Ok(materials)
+}
+
+struct MtlProperties {
+    diffuse: DVec3,
+    specular: DVec3,
+    emission: DVec3,
+    shininess: f64,
+    refractive_index: f64,
+    dissolve: f64,
+    illumination: u32,
+}
+
+impl Default for MtlProperties {
+    fn default() -> Self {
+        Self {
+            diffuse: DVec3::splat(0.8),
+            specular: DVec3::ZERO,
+            emission: DVec3::ZERO,
+            shininess: 0.0,
+            refractive_index: 1.5,
+            dissolve: 1.0,
+            illumination: 2,
+        }
+    }
+}
+
+impl MtlProperties {
+    fn to_material(&self) -> Material {
+        if self.emission != DVec3::ZERO {
+            return Material::DiffuseLight {
+                emit: self.emission,
+            };
+        }
+
+        let refracts = matches!(self.illumination, 4 | 6 | 7 | 9);
+        if self.dissolve < 1.0 || refracts {
+            return Material::Dielectric {
+                refractive_index: self.refractive_index,
+            };
+        }
+
+        let mirror = matches!(self.illumination, 3 | 5);
+        if mirror || self.specular.element_sum() > self.diffuse.element_sum() {
+            // approximate roughness from the Phong exponent
+            let fuzz = (2.0 / (self.shininess.max(0.0) + 2.0)).sqrt();
+            return Material::Metal {
+                albedo: Texture::Solid(self.specular),
+                fuzz,
+            };
+        }
+
+        Material::Lambertian {
+            albedo: Texture::Solid(self.diffuse),
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Splits a line into its keyword and the rest, skipping blank lines and
+/// comments.
+fn split_keyword(line: &str) -> Option<(&str, &str)> {
+    let line = line.split('#').next().unwrap_or("").trim();
+    if line.is_empty() {
+        return None;
+    }
+    Some(line.split_once(char::is_whitespace).unwrap_or((line, "")))
+}
+
+/// Parses one `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner. Indices are
+/// 1-based, negative indices count back from the last element read so far.
+fn parse_vertex(corner: &str, data: &MeshData, line: usize) -> Result<MeshVertex, ObjError> {
+    let mut parts = corner.split('/');
+    let position = parts
+        .next()
+        .filter(|part| !part.is_empty())
+        .ok_or_else(|| parse_error(line, "face vertex is missing its position"))?;
+    let texcoord = parts.next().filter(|part| !part.is_empty());
+    let normal = parts.next().filter(|part| !part.is_empty());
+
+    Ok(MeshVertex {
+        position: resolve_index(position, data.positions.len(), line)?,
+        texcoord: texcoord
+            .map(|index| resolve_index(index, data.texcoords.len(), line))
+            .transpose()?,
+        normal: normal
+            .map(|index| resolve_index(index, data.normals.len(), line))
+            .transpose()?,
+    })
+}
+
+fn resolve_index(index: &str, len: usize, line: usize) -> Result<usize, ObjError> {
+    let value: i64 = index
+        .parse()
+        .map_err(|_| parse_error(line, &format!("invalid index '{}'", index)))?;
+    let resolved = if value < 0 {
+        len as i64 + value
+    } else {
+        value - 1
+    };
+
+    if value == 0 || resolved < 0 || resolved >= len as i64 {
+        return Err(parse_error(
+            line,
+            &format!("index {} is out of range", value),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_floats(values: &str, line: usize) -> Result<Vec<f64>, ObjError> {
+    values
+        .split_whitespace()
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| parse_error(line, &format!("invalid number '{}'", value)))
+        })
+        .collect()
+}
+
+fn parse_float(values: &str, line: usize) -> Result<f64, ObjError> {
+    parse_floats(values, line)?
+        .first()
+        .copied()
+        .ok_or_else(|| parse_error(line, "expected a number"))
+}
+
+fn parse_dvec3(values: &str, line: usize) -> Result<DVec3, ObjError> {
+    match parse_floats(values, line)?.as_slice() {
+        [x, y, z, ..] => Ok(DVec3::new(*x, *y, *z)),
+        _ => Err(parse_error(line, "expected 3 numbers")),
+    }
+}
+
+fn parse_error(line: usize, message: &str) -> ObjError {
+    ObjError::Parse {
+        line,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREY: Material = Material::Lambertian {
+        albedo: Texture::Solid(DVec3::splat(0.5)),
+    };
+
+    #[test]
+    fn test_polygon_is_triangulated() {
+        let source = "
+            # a unit square
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vt 0 0
+            vt 1 0
+            vt 1 1
+            vt 0 1
+            vn 0 0 1
+            f 1/1/1 2/2/1 3/3/1 4/4/1
+        ";
+        let data = parse_obj(source, &HashMap::new(), GREY).unwrap();
+        assert_eq!(data.positions.len(), 4);
+        assert_eq!(data.faces.len(), 2);
+        assert_eq!(data.faces[1].vertices[0].position, 0);
+        assert_eq!(data.faces[1].vertices[1].position, 2);
+        assert_eq!(data.faces[1].vertices[2].texcoord, Some(3));
+        assert_eq!(data.faces[1].vertices[2].normal, Some(0));
+    }
+
+    #[test]
+    fn test_negative_and_missing_indices() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf -3//-1 -2//-1 -1//-1\n";
+        let data = parse_obj(source, &HashMap::new(), GREY).unwrap();
+        let vertices = data.faces[0].vertices;
+        assert_eq!(vertices.map(|v| v.position), [0, 1, 2]);
+        assert_eq!(vertices.map(|v| v.normal), [Some(0); 3]);
+        assert_eq!(vertices.map(|v| v.texcoord), [None; 3]);
+    }
+
+    #[test]
+    fn test_invalid_index() {
+        let source = "v 0 0 0\nv 1 0 0\nf 1 2 3\n";
+        match parse_obj(source, &HashMap::new(), GREY) {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 3),
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn test_mtl_materials() {
+        let source = "
+            newmtl clay
+            Kd 0.8 0.2 0.1
+            newmtl chrome
+            Kd 0.1 0.1 0.1
+            Ks 0.9 0.9 0.9
+            Ns 998
+            newmtl glass
+            Ni 1.45
+            d 0.1
+            newmtl lamp
+            Kd 1 1 1
+            Ke 10 9 8
+        ";
+        let materials = parse_mtl(source).unwrap();
+        assert!(matches!(
+            materials["clay"],
+            Material::Lambertian { albedo: Texture::Solid(albedo) } if albedo == DVec3::new(0.8, 0.2, 0.1)
+        ));
+        assert!(matches!(
+            materials["chrome"],
+            Material::Metal { fuzz, .. } if fuzz < 0.05
+        ));
+        assert!(matches!(
+            materials["glass"],
+            Material::Dielectric { refractive_index } if refractive_index == 1.45
+        ));
+        assert!(matches!(
+            materials["lamp"],
+            Material::DiffuseLight { emit } if emit == DVec3::new(10., 9., 8.)
+        ));
+    }
+
+    #[test]
+    fn test_usemtl_assigns_materials() {
+        let materials = parse_mtl("newmtl red\nKd 1 0 0\n").unwrap();
+        let source =
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nusemtl red\nf 1 2 3\nusemtl unknown\nf 1 2 3\n";
+        let data = parse_obj(source, &materials, GREY).unwrap();
+        assert_eq!(data.materials.len(), 2);
+        assert_eq!(
+            data.faces.iter().map(|f| f.material).collect::<Vec<_>>(),
+            [0, 1, 0]
+        );
+    }
+}
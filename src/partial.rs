@@ -0,0 +1,414 @@
+//! Partial renders: what one render of a scene accumulated, kept so that
+//! renders of the same scene with different seeds, e.g. on different
+//! machines, can be merged into one image.
+//!
+//! A partial render file holds fingerprints of the scene and the camera's
+//! view, the samples that went into it, the image size, filter and AOVs,
+//! then every pixel's filter weighted radiance sum, weight and sample count
+//! as little endian floats and integers. Merging adds the sums up, so every
+//! render counts in proportion to the samples it took.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::Range,
+    path::Path,
+};
+
+use crate::{
+    accumulator::{read_u32, read_u64, write_u32, write_u64, Accumulator},
+    aov::Aov,
+    camera::Camera,
+    filter::{check_radius, Filter, FilterKind},
+};
+
+const MAGIC: &[u8; 8] = b"AURORAPR";
+const VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum PartialRenderError {
+    Io(io::Error),
+    NotAPartialRender,
+    UnsupportedVersion(u32),
+    /// An AOV or filter this version doesn't know.
+    UnknownName(String),
+    /// The renders are of different scene files.
+    SceneMismatch,
+    /// The renders had different camera settings besides the seed and the
+    /// samples, see `Camera::view_fingerprint`.
+    ViewMismatch,
+    /// Both renders took some of the same samples with the same sampler
+    /// and seed, so merging would count them twice.
+    Overlap,
+}
+
+impl fmt::Display for PartialRenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartialRenderError::Io(error) => write!(f, "{}", error),
+            PartialRenderError::NotAPartialRender => write!(f, "not a partial render"),
+            PartialRenderError::UnsupportedVersion(version) => {
+                write!(f, "unsupported partial render version {}", version)
+            }
+            PartialRenderError::UnknownName(name) => write!(f, "unknown AOV or filter '{}'", name),
+            PartialRenderError::SceneMismatch => write!(f, "rendered from a different scene"),
+            PartialRenderError::ViewMismatch => {
+                write!(f, "rendered with different camera settings")
+            }
+            PartialRenderError::Overlap => {
+                write!(
+                    f,
+                    "shares samples with another render, use a different seed"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PartialRenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PartialRenderError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PartialRenderError {
+    fn from(error: io::Error) -> Self {
+        PartialRenderError::Io(error)
+    }
+}
+
+/// Sample indices taken with one sampler and seed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampleRange {
+    /// See `Camera::fingerprint`.
+    pub camera: u64,
+    pub samples: Range<u32>,
+}
+
+impl SampleRange {
+    fn overlaps(&self, other: &SampleRange) -> bool {
+        self.camera == other.camera
+            && self.samples.start < other.samples.end
+            && other.samples.start < self.samples.end
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PartialRender {
+    /// See `Scene::fingerprint`.
+    pub scene: u64,
+    /// See `Camera::view_fingerprint`.
+    pub view: u64,
+    /// The samples that went into the render, sorted and with touching
+    /// ranges joined.
+    pub samples: Vec<SampleRange>,
+    pub accumulator: Accumulator,
+}
+
+impl PartialRender {
+    /// What `camera` accumulated taking samples `samples` of the scene with
+    /// fingerprint `scene`.
+    pub fn new(scene: u64, camera: &Camera, samples: Range<u32>, accumulator: Accumulator) -> Self {
+        Self {
+            scene,
+            view: camera.view_fingerprint(),
+            samples: vec![SampleRange {
+                camera: camera.fingerprint(),
+                samples,
+            }],
+            accumulator,
+        }
+    }
+
+    /// Adds the samples of `other`, if it is a render of the same scene
+    /// and view and none of its samples are in this one already.
+    pub fn merge(&mut self, other: &PartialRender) -> Result<(), PartialRenderError> {
+        if other.scene != self.scene {
+            return Err(PartialRenderError::SceneMismatch);
+        }
+        let (a, b) = (&self.accumulator, &other.accumulator);
+        if other.view != self.view
+            || a.width() != b.width()
+            || a.height() != b.height()
+            || a.aovs != b.aovs
+        {
+            return Err(PartialRenderError::ViewMismatch);
+        }
+        let overlap = self
+            .samples
+            .iter()
+            .any(|range| other.samples.iter().any(|other| range.overlaps(other)));
+        if overlap {
+            return Err(PartialRenderError::Overlap);
+        }
+        self.accumulator.merge(&other.accumulator);
+
+        self.samples.extend(other.samples.iter().cloned());
+        self.samples
+            .sort_by_key(|range| (range.camera, range.samples.start));
+        self.samples.dedup_by(|next, previous| {
+            // consecutive jobs of a distributed render become one range
+            let touching =
+                next.camera == previous.camera && next.samples.start == previous.samples.end;
+            if touching {
+                previous.samples.end = next.samples.end;
+            }
+            touching
+        });
+        Ok(())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PartialRenderError> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let accumulator = &self.accumulator;
+        writer.write_all(MAGIC)?;
+        write_u32(writer, VERSION)?;
+        write_u64(writer, self.scene)?;
+        write_u64(writer, self.view)?;
+        write_u32(writer, self.samples.len() as u32)?;
+        for range in &self.samples {
+            write_u64(writer, range.camera)?;
+            write_u32(writer, range.samples.start)?;
+            write_u32(writer, range.samples.end)?;
+        }
+        write_u32(writer, accumulator.width())?;
+        write_u32(writer, accumulator.height())?;
+        write_u32(writer, accumulator.samples_taken)?;
+        let filter = accumulator.film.filter();
+        write_name(writer, filter.kind.name())?;
+        write_u64(writer, filter.radius.to_bits())?;
+        write_u32(writer, accumulator.aovs.len() as u32)?;
+        for aov in &accumulator.aovs {
+            write_name(writer, aov.name())?;
+        }
+        accumulator.write_pixels(writer)
+    }
+
+    pub fn read(reader: &mut impl Read) -> Result<Self, PartialRenderError> {
+        Self::read_expecting(reader, None)
+    }
+
+    /// Like `read`, but fails with `ViewMismatch` as soon as the header
+    /// shows a render of something other than `camera`'s image, before
+    /// making room for the pixels.
+    pub fn read_for(reader: &mut impl Read, camera: &Camera) -> Result<Self, PartialRenderError> {
+        Self::read_expecting(reader, Some(camera))
+    }
+
+    fn read_expecting(
+        reader: &mut impl Read,
+        camera: Option<&Camera>,
+    ) -> Result<Self, PartialRenderError> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(PartialRenderError::NotAPartialRender);
+        }
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(PartialRenderError::UnsupportedVersion(version));
+        }
+        let scene = read_u64(reader)?;
+        let view = read_u64(reader)?;
+        let samples = (0..read_u32(reader)?)
+            .map(|_| {
+                let camera = read_u64(reader)?;
+                let samples = read_u32(reader)?..read_u32(reader)?;
+                if samples.start > samples.end {
+                    return Err(PartialRenderError::NotAPartialRender);
+                }
+                Ok(SampleRange { camera, samples })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let width = read_u32(reader)?;
+        let height = read_u32(reader)?;
+        let samples_taken = read_u32(reader)?;
+        let filter_name = read_name(reader)?;
+        let kind = FilterKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == filter_name)
+            .ok_or(PartialRenderError::UnknownName(filter_name))?;
+        let radius = check_radius(f64::from_bits(read_u64(reader)?))
+            .map_err(|_| PartialRenderError::NotAPartialRender)?;
+        let filter = Filter::with_radius(kind, radius);
+        let aovs = (0..read_u32(reader)?)
+            .map(|_| {
+                let name = read_name(reader)?;
+                Aov::ALL
+                    .into_iter()
+                    .find(|aov| aov.name() == name)
+                    .ok_or(PartialRenderError::UnknownName(name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(camera) = camera {
+            let expected = (
+                camera.view_fingerprint(),
+                camera.image_width(),
+                camera.image_height(),
+                camera.filter(),
+                camera.aovs(),
+            );
+            if (view, width, height, filter, aovs.as_slice()) != expected {
+                return Err(PartialRenderError::ViewMismatch);
+            }
+        }
+
+        let mut accumulator = Accumulator::new(width, height, filter, &aovs);
+        accumulator.samples_taken = samples_taken;
+        accumulator.read_pixels(reader)?;
+        Ok(Self {
+            scene,
+            view,
+            samples,
+            accumulator,
+        })
+    }
+}
+
+fn write_name(writer: &mut impl Write, name: &str) -> io::Result<()> {
+    writer.write_all(&[name.len() as u8])?;
+    writer.write_all(name.as_bytes())
+}
+
+fn read_name(reader: &mut impl Read) -> io::Result<String> {
+    let mut length = [0];
+    reader.read_exact(&mut length)?;
+    let mut name = vec![0; length[0] as usize];
+    reader.read_exact(&mut name)?;
+    Ok(String::from_utf8_lossy(&name).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::{Background, CameraBuilder},
+        hittable::HittableList,
+        material::Material,
+        shapes::sphere::Sphere,
+        texture::Texture,
+    };
+    use glam::DVec3;
+
+    fn camera(camera: CameraBuilder) -> Camera {
+        camera
+            .image_width(12)
+            .look_from(DVec3::ZERO)
+            .look_at(DVec3::new(0., 0., -1.))
+            .background(Background::Sky)
+            .samples_per_pixel(2)
+            .aovs([Aov::SampleCount])
+            .build()
+    }
+
+    fn partial(camera: CameraBuilder, samples: Range<u32>) -> PartialRender {
+        let mut world = HittableList { objects: vec![] };
+        world.objects.push(Box::new(Sphere {
+            center: DVec3::new(0.0, 0.0, -1.0),
+            radius: 0.5,
+            material: Material::Lambertian {
+                albedo: Texture::Solid(DVec3::splat(0.5)),
+            },
+        }));
+        let camera = self::camera(camera);
+        let mut accumulator = camera.accumulator();
+        camera.render_samples(
+            &world,
+            &HittableList::default(),
+            &mut accumulator,
+            samples.clone(),
+        );
+        PartialRender::new(7, &camera, samples, accumulator)
+    }
+
+    #[test]
+    fn test_merge_weights_by_samples() {
+        let a = partial(CameraBuilder::new().seed(1), 0..2);
+        let b = partial(CameraBuilder::new().seed(2), 0..2);
+        let mut merged = a.clone();
+        merged.merge(&b).unwrap();
+        assert_eq!(merged.samples.len(), 2);
+
+        let (a, b) = (a.accumulator.output(), b.accumulator.output());
+        let merged = merged.accumulator.output();
+        assert_ne!(a.beauty, b.beauty);
+        for ((a, b), merged) in a
+            .beauty
+            .pixels()
+            .iter()
+            .zip(b.beauty.pixels())
+            .zip(merged.beauty.pixels())
+        {
+            assert!((*merged - (*a + *b) / 2.0).length() < 1e-12);
+        }
+        let samples = merged.aov(Aov::SampleCount).unwrap();
+        assert_eq!(samples.get(5, 3), DVec3::splat(4.0));
+    }
+
+    #[test]
+    fn test_disjoint_samples_of_one_seed() {
+        let mut merged = partial(CameraBuilder::new().seed(1), 0..2);
+        merged
+            .merge(&partial(CameraBuilder::new().seed(1), 2..4))
+            .unwrap();
+        assert_eq!(merged.samples.len(), 1);
+        assert_eq!(merged.samples[0].samples, 0..4);
+        assert!(matches!(
+            merged.merge(&partial(CameraBuilder::new().seed(1), 3..5)),
+            Err(PartialRenderError::Overlap)
+        ));
+    }
+
+    #[test]
+    fn test_round_trip_and_mismatches() {
+        let a = partial(CameraBuilder::new(), 0..2);
+        let mut bytes = vec![];
+        a.write(&mut bytes).unwrap();
+        let read = PartialRender::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!((read.scene, read.view), (a.scene, a.view));
+        assert_eq!(read.samples, a.samples);
+        assert_eq!(
+            read.accumulator.output().beauty,
+            a.accumulator.output().beauty
+        );
+        assert_eq!(read.accumulator.samples_taken(), 2);
+        let same = camera(CameraBuilder::new());
+        assert!(PartialRender::read_for(&mut bytes.as_slice(), &same).is_ok());
+        let tent = camera(CameraBuilder::new().filter(FilterKind::Tent));
+        assert!(matches!(
+            PartialRender::read_for(&mut bytes.as_slice(), &tent),
+            Err(PartialRenderError::ViewMismatch)
+        ));
+
+        let mut other_scene = partial(CameraBuilder::new(), 0..2);
+        other_scene.scene = 8;
+        let other_view = partial(CameraBuilder::new().vertical_fov(30.0), 0..2);
+        let mut merged = a.clone();
+        assert!(matches!(
+            merged.merge(&other_scene),
+            Err(PartialRenderError::SceneMismatch)
+        ));
+        assert!(matches!(
+            merged.merge(&other_view),
+            Err(PartialRenderError::ViewMismatch)
+        ));
+        assert!(matches!(merged.merge(&a), Err(PartialRenderError::Overlap)));
+        assert!(matches!(
+            PartialRender::read(&mut &b"AURORACK"[..]),
+            Err(PartialRenderError::NotAPartialRender)
+        ));
+    }
+}
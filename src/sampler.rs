@@ -0,0 +1,439 @@
+//! Sample generators for the random numbers of each camera sample.
+//!
+//! A sampler maps (pixel, sample index, dimension) to a number in [0, 1).
+//! While the camera traces a sample it installs the sampler on the thread,
+//! and every `fastrand::random_f64` call takes the next dimension: the
+//! first two pick the spot in the pixel, the next two the spot on the lens
+//! when there is defocus blur, then the rest go to the bounces. Samplers
+//! that spread the samples of a pixel evenly in each dimension converge
+//! faster than independent random numbers.
+
+use std::{cell::RefCell, fmt, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fastrand::mix64;
+
+pub trait Sampler: fmt::Debug + Send + Sync {
+    /// Coordinate `dimension` of sample `index` of pixel `pixel`, in [0, 1).
+    fn sample(&self, pixel: u64, index: u64, dimension: u32) -> f64;
+}
+
+/// The built-in samplers, as picked in a scene file or on the command line.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    #[default]
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub const ALL: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+        }
+    }
+
+    pub fn build(self, seed: u64, samples_per_pixel: u32) -> Arc<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Arc::new(IndependentSampler { seed }),
+            SamplerKind::Stratified => Arc::new(StratifiedSampler {
+                seed,
+                samples_per_pixel,
+            }),
+            SamplerKind::Halton => Arc::new(HaltonSampler { seed }),
+            SamplerKind::Sobol => Arc::new(SobolSampler { seed }),
+        }
+    }
+}
+
+/// Unrelated uniform random numbers for every dimension.
+#[derive(Clone, Debug)]
+pub struct IndependentSampler {
+    pub seed: u64,
+}
+
+impl Sampler for IndependentSampler {
+    fn sample(&self, pixel: u64, index: u64, dimension: u32) -> f64 {
+        to_unit(hash(&[self.seed, pixel, index, dimension as u64]))
+    }
+}
+
+/// Jittered grid: each pair of dimensions is split into about
+/// `samples_per_pixel` cells, and every sample of a pixel lands in a
+/// different cell, picked in a random order per pair.
+#[derive(Clone, Debug)]
+pub struct StratifiedSampler {
+    pub seed: u64,
+    pub samples_per_pixel: u32,
+}
+
+impl Sampler for StratifiedSampler {
+    fn sample(&self, pixel: u64, index: u64, dimension: u32) -> f64 {
+        let columns = (self.samples_per_pixel.max(1) as f64).sqrt() as u32;
+        let rows = self.samples_per_pixel.max(1).div_ceil(columns);
+        let cells = columns * rows;
+
+        // samples beyond the grid start another round in a new order
+        let pair = (dimension / 2) as u64;
+        let round = index / cells as u64;
+        let order = hash(&[self.seed, pixel, pair, round]) as u32;
+        let cell = permute((index % cells as u64) as u32, cells, order);
+
+        let jitter = to_unit(hash(&[self.seed, pixel, index, dimension as u64]));
+        if dimension.is_multiple_of(2) {
+            ((cell % columns) as f64 + jitter) / columns as f64
+        } else {
+            ((cell / columns) as f64 + jitter) / rows as f64
+        }
+    }
+}
+
+/// The Halton sequence, with a prime base per dimension and a random shift
+/// per pixel. Dimensions past the prime table fall back to independent
+/// random numbers.
+#[derive(Clone, Debug)]
+pub struct HaltonSampler {
+    pub seed: u64,
+}
+
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+impl Sampler for HaltonSampler {
+    fn sample(&self, pixel: u64, index: u64, dimension: u32) -> f64 {
+        let shift = to_unit(hash(&[self.seed, pixel, dimension as u64]));
+        let Some(base) = PRIMES.get(dimension as usize) else {
+            return to_unit(hash(&[self.seed, pixel, index, dimension as u64]));
+        };
+        // Cranley-Patterson rotation, kept below 1 despite rounding
+        let value = radical_inverse(index, *base) + shift;
+        let value = if value >= 1.0 { value - 1.0 } else { value };
+        value.min(ONE_MINUS_EPSILON)
+    }
+}
+
+/// Owen-scrambled Sobol points, after Burley's "Practical Hash-based Owen
+/// Scrambling". Dimensions come in groups of four, each with its own
+/// shuffle of the sample indices, so the first samples of a pixel are well
+/// spread in every pair of dimensions within a group.
+#[derive(Clone, Debug)]
+pub struct SobolSampler {
+    pub seed: u64,
+}
+
+impl Sampler for SobolSampler {
+    fn sample(&self, pixel: u64, index: u64, dimension: u32) -> f64 {
+        let group_seed = hash(&[self.seed, pixel, (dimension / 4) as u64]) as u32;
+        let index = nested_uniform_scramble(index as u32, group_seed);
+        let component = (dimension % 4) as usize;
+        let value = nested_uniform_scramble(
+            sobol(index, component),
+            mix64(group_seed as u64 ^ component as u64) as u32,
+        );
+        value as f64 / 4_294_967_296.0
+    }
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x2545_f491_4f6c_dd1d, |hash, value| mix64(hash ^ value))
+}
+
+/// The top 53 bits of `bits` as a number in [0, 1).
+fn to_unit(bits: u64) -> f64 {
+    (bits >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+/// The digits of `index` in `base`, mirrored around the decimal point.
+fn radical_inverse(mut index: u64, base: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut scale = inverse_base;
+    let mut value = 0.0;
+    while index > 0 {
+        value += (index % base) as f64 * scale;
+        index /= base;
+        scale *= inverse_base;
+    }
+    value
+}
+
+/// Kensler's hashed permutation of [0, `length`) from "Correlated
+/// Multi-Jittered Sampling".
+fn permute(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+    i.wrapping_add(seed) % length
+}
+
+/// Direction numbers of the first four Sobol dimensions.
+const SOBOL_DIRECTIONS: [[u32; 32]; 4] = [
+    sobol_directions(0, 0, &[]),
+    sobol_directions(1, 0, &[1]),
+    sobol_directions(2, 1, &[1, 3]),
+    sobol_directions(3, 1, &[1, 3, 1]),
+];
+
+/// Direction numbers for the primitive polynomial of `degree` with inner
+/// coefficients `a` and initial values `m`, from Joe and Kuo's table. Degree
+/// 0 is the van der Corput sequence.
+const fn sobol_directions(degree: usize, a: u32, m: &[u32]) -> [u32; 32] {
+    let mut directions = [0; 32];
+    let mut i = 0;
+    while i < 32 {
+        directions[i] = if degree == 0 {
+            1 << (31 - i)
+        } else if i < degree {
+            m[i] << (31 - i)
+        } else {
+            let mut value = directions[i - degree] ^ (directions[i - degree] >> degree);
+            let mut k = 1;
+            while k < degree {
+                if (a >> (degree - 1 - k)) & 1 == 1 {
+                    value ^= directions[i - k];
+                }
+                k += 1;
+            }
+            value
+        };
+        i += 1;
+    }
+    directions
+}
+
+fn sobol(index: u32, dimension: usize) -> u32 {
+    let mut value = 0;
+    for (bit, direction) in SOBOL_DIRECTIONS[dimension].iter().enumerate() {
+        if (index >> bit) & 1 == 1 {
+            value ^= direction;
+        }
+    }
+    value
+}
+
+/// Owen scrambling: flips each bit depending on the bits above it.
+fn nested_uniform_scramble(value: u32, seed: u32) -> u32 {
+    laine_karras_permutation(value.reverse_bits(), seed).reverse_bits()
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+/// The camera sample being traced on this thread, if any.
+struct ActiveSample {
+    sampler: Option<Arc<dyn Sampler>>,
+    tracing: bool,
+    pixel: u64,
+    index: u64,
+    dimension: u32,
+}
+
+thread_local! {
+    static ACTIVE_SAMPLE: RefCell<ActiveSample> = const {
+        RefCell::new(ActiveSample {
+            sampler: None,
+            tracing: false,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        })
+    };
+}
+
+/// Makes `fastrand::random_f64` on this thread draw the dimensions of
+/// sample `index` of `pixel` from `sampler`, starting at dimension 0,
+/// until `end_sample`.
+pub fn begin_sample(sampler: &Arc<dyn Sampler>, pixel: u64, index: u64) {
+    ACTIVE_SAMPLE.with(|active| {
+        let mut active = active.borrow_mut();
+        // the sampler is kept between samples so the reference count isn't
+        // touched every time
+        if !active
+            .sampler
+            .as_ref()
+            .is_some_and(|current| Arc::ptr_eq(current, sampler))
+        {
+            active.sampler = Some(Arc::clone(sampler));
+        }
+        active.tracing = true;
+        active.pixel = pixel;
+        active.index = index;
+        active.dimension = 0;
+    })
+}
+
+/// Hands `fastrand::random_f64` back to the thread RNG.
+pub fn end_sample() {
+    ACTIVE_SAMPLE.with(|active| active.borrow_mut().tracing = false)
+}
+
+/// The next dimension of the sample being traced, or `None` outside of
+/// `begin_sample` and `end_sample`.
+pub(crate) fn next_dimension() -> Option<f64> {
+    ACTIVE_SAMPLE.with(|active| {
+        let mut active = active.borrow_mut();
+        if !active.tracing {
+            return None;
+        }
+        let dimension = active.dimension;
+        active.dimension += 1;
+        let sampler = active.sampler.as_ref()?;
+        Some(sampler.sample(active.pixel, active.index, dimension))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fastrand::random_f64;
+
+    /// Which cell of an `n` by `n` grid each of the first `n * n` samples of
+    /// a pixel falls into, for dimensions 0 and 1.
+    fn cells(sampler: &dyn Sampler, pixel: u64, n: u32) -> Vec<u32> {
+        let mut cells: Vec<u32> = (0..(n * n) as u64)
+            .map(|index| {
+                let x = (sampler.sample(pixel, index, 0) * n as f64) as u32;
+                let y = (sampler.sample(pixel, index, 1) * n as f64) as u32;
+                y * n + x
+            })
+            .collect();
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn test_samples_in_unit_interval() {
+        for kind in SamplerKind::ALL {
+            let sampler = kind.build(1, 16);
+            for index in 0..64 {
+                for dimension in 0..80 {
+                    let value = sampler.sample(3, index, dimension);
+                    assert!((0.0..1.0).contains(&value), "{:?} gave {}", kind, value);
+                    assert_eq!(value, sampler.sample(3, index, dimension));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stratified_samples_fill_grid() {
+        let all: Vec<u32> = (0..16).collect();
+        let stratified = StratifiedSampler {
+            seed: 5,
+            samples_per_pixel: 16,
+        };
+        let sobol = SobolSampler { seed: 5 };
+        for pixel in 0..8 {
+            assert_eq!(cells(&stratified, pixel, 4), all);
+            assert_eq!(cells(&sobol, pixel, 4), all);
+        }
+    }
+
+    #[test]
+    fn test_radical_inverse() {
+        assert_eq!(radical_inverse(1, 2), 0.5);
+        assert_eq!(radical_inverse(6, 2), 0.375);
+        assert!((radical_inverse(5, 3) - 7.0 / 9.0).abs() < 1e-12);
+        assert_eq!(
+            SOBOL_DIRECTIONS[1][..3],
+            [0x80000000, 0xc0000000, 0xa0000000]
+        );
+    }
+
+    #[test]
+    fn test_low_discrepancy_converges_faster() {
+        // mean squared error of estimating the integral of x * y over the
+        // unit square, 1/4, with 64 samples
+        let error = |sampler: &dyn Sampler| {
+            (0..256)
+                .map(|pixel| {
+                    let estimate = (0..64)
+                        .map(|index| {
+                            sampler.sample(pixel, index, 0) * sampler.sample(pixel, index, 1)
+                        })
+                        .sum::<f64>()
+                        / 64.0;
+                    (estimate - 0.25).powi(2)
+                })
+                .sum::<f64>()
+        };
+        let independent = error(&IndependentSampler { seed: 0 });
+        for kind in [
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ] {
+            let better = error(kind.build(0, 64).as_ref());
+            assert!(
+                better * 4.0 < independent,
+                "{:?}: {} vs {}",
+                kind,
+                better,
+                independent
+            );
+        }
+    }
+
+    #[test]
+    fn test_random_f64_follows_active_sample() {
+        let sampler = SamplerKind::Halton.build(2, 4);
+        begin_sample(&sampler, 7, 3);
+        let drawn = [random_f64(), random_f64()];
+        end_sample();
+        assert_eq!(drawn, [sampler.sample(7, 3, 0), sampler.sample(7, 3, 1)]);
+        assert_eq!(next_dimension(), None);
+    }
+}
@@ -0,0 +1,728 @@
+//! Declarative scene files.
+//!
+//! A scene is a TOML document with a `[camera]` table (any `CameraBuilder`
+//! field), `[render]` settings, named `[textures.*]` and `[materials.*]`,
+//! and a list of `[[shapes]]` that refer to materials by name:
+//!
+//! ```toml
+//! [camera]
+//! image_width = 400
+//! look_from = [13.0, 2.0, 3.0]
+//! background = { solid = [0.0, 0.0, 0.0] }
+//! tone_map = { curve = "aces", exposure = 0.5 }
+//!
+//! [render]
+//! output = "spheres.png"
+//!
+//! [textures.checker]
+//! type = "checker"
+//! scale = 0.32
+//! even = [0.2, 0.3, 0.1]
+//! odd = [0.9, 0.9, 0.9]
+//!
+//! [materials.ground]
+//! type = "lambertian"
+//! albedo = "checker"
+//!
+//! [materials.glass]
+//! type = "dielectric"
+//! refractive_index = 1.5
+//!
+//! [[shapes]]
+//! type = "sphere"
+//! center = [0.0, -1000.0, 0.0]
+//! radius = 1000.0
+//! material = "ground"
+//! ```
+//!
+//! Relative paths (meshes, image textures, the output) are resolved against
+//! the directory of the scene file.
+
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use glam::{DAffine3, DVec3};
+use serde::Deserialize;
+
+use crate::{
+    camera::CameraBuilder,
+    fastrand::{hash_bytes, hash_values},
+    hittable::{Hittable, HittableList},
+    material::Material,
+    obj::{load_obj_data, ObjError},
+    shapes::{
+        mesh::TriangleMesh,
+        quad::{cuboid, Quad},
+        sphere::Sphere,
+        triangle::Triangle,
+    },
+    texture::{
+        image::ImageTexture,
+        noise::{NoiseKind, NoiseTexture},
+        Texture,
+    },
+};
+
+/// The contents of a scene file, before anything is built.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneDescription {
+    pub camera: CameraBuilder,
+    pub render: RenderSettings,
+    pub textures: HashMap<String, TextureDescription>,
+    pub materials: HashMap<String, MaterialDescription>,
+    pub shapes: Vec<ShapeDescription>,
+    /// A hash of the text `parse` read the description from.
+    #[serde(skip)]
+    pub fingerprint: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    /// Where the rendered image is written. The extension picks the format.
+    pub output: PathBuf,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            output: PathBuf::from("output.png"),
+        }
+    }
+}
+
+/// Either a constant colour or the name of an entry in `[textures]`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum TextureRef {
+    Color(DVec3),
+    Named(String),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TextureDescription {
+    Solid {
+        color: DVec3,
+    },
+    Checker {
+        scale: f64,
+        even: TextureRef,
+        odd: TextureRef,
+    },
+    Image {
+        path: PathBuf,
+    },
+    Noise {
+        kind: NoiseKindDescription,
+        #[serde(default = "default_noise_scale")]
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+        #[serde(default = "default_noise_color")]
+        color: DVec3,
+    },
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseKindDescription {
+    Perlin,
+    Turbulence,
+    Marble,
+}
+
+fn default_noise_scale() -> f64 {
+    1.0
+}
+
+fn default_noise_color() -> DVec3 {
+    DVec3::ONE
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDescription {
+    Lambertian {
+        albedo: TextureRef,
+    },
+    Metal {
+        albedo: TextureRef,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    Dielectric {
+        refractive_index: f64,
+    },
+    DiffuseLight {
+        emit: DVec3,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ShapeDescription {
+    Sphere {
+        center: DVec3,
+        radius: f64,
+        material: String,
+    },
+    /// Parallelogram with corner `q` and edges `u` and `v`.
+    Quad {
+        q: DVec3,
+        u: DVec3,
+        v: DVec3,
+        material: String,
+    },
+    Triangle {
+        a: DVec3,
+        b: DVec3,
+        c: DVec3,
+        material: String,
+    },
+    /// Axis aligned box between two opposite corners.
+    Box {
+        min: DVec3,
+        max: DVec3,
+        material: String,
+    },
+    /// Wavefront OBJ mesh. `material` overrides faces without an MTL
+    /// material; the mesh is scaled, then translated.
+    Mesh {
+        path: PathBuf,
+        material: Option<String>,
+        #[serde(default = "default_mesh_scale")]
+        scale: f64,
+        #[serde(default)]
+        translate: DVec3,
+    },
+}
+
+fn default_mesh_scale() -> f64 {
+    1.0
+}
+
+/// A loaded scene, ready to render.
+pub struct Scene {
+    /// Camera settings from the file. Adjust before calling `build`.
+    pub camera: CameraBuilder,
+    pub world: HittableList,
+    /// The shapes with an emissive material, for light sampling. Meshes
+    /// are left out: their faces can't be sampled yet.
+    pub lights: HittableList,
+    pub render: RenderSettings,
+    /// A hash of the scene file and the meshes and images it loads, to tell
+    /// whether a checkpoint or partial render was rendered from it.
+    pub fingerprint: u64,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse(toml::de::Error),
+    UnknownTexture(String),
+    /// A checker that contains itself, directly or through other checkers.
+    TextureCycle(String),
+    UnknownMaterial(String),
+    Mesh(ObjError),
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Parse(error) => write!(f, "invalid scene file: {}", error),
+            SceneError::UnknownTexture(name) => write!(f, "unknown texture '{}'", name),
+            SceneError::TextureCycle(name) => write!(f, "texture '{}' refers to itself", name),
+            SceneError::UnknownMaterial(name) => write!(f, "unknown material '{}'", name),
+            SceneError::Mesh(error) => write!(f, "could not load mesh: {}", error),
+            SceneError::Image { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Parse(error) => Some(error),
+            SceneError::Mesh(error) => Some(error),
+            SceneError::Image { source, .. } => Some(source),
+            SceneError::UnknownTexture(_)
+            | SceneError::TextureCycle(_)
+            | SceneError::UnknownMaterial(_) => None,
+        }
+    }
+}
+
+/// Reads and builds the scene file at `path`.
+pub fn load_scene(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|source| SceneError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+
+    SceneDescription::parse(&source)?.build(base_dir)
+}
+
+impl SceneDescription {
+    pub fn parse(source: &str) -> Result<Self, SceneError> {
+        let mut description: Self = toml::from_str(source).map_err(SceneError::Parse)?;
+        description.fingerprint = hash_bytes(source.as_bytes());
+        Ok(description)
+    }
+
+    /// The meshes and images the scene loads, as written in the file.
+    pub fn asset_paths(&self) -> Vec<&Path> {
+        let images = self.textures.values().filter_map(|texture| match texture {
+            TextureDescription::Image { path } => Some(path.as_path()),
+            _ => None,
+        });
+        let meshes = self.shapes.iter().filter_map(|shape| match shape {
+            ShapeDescription::Mesh { path, .. } => Some(path.as_path()),
+            _ => None,
+        });
+        images.chain(meshes).collect()
+    }
+
+    /// Creates the textures, materials and shapes. Relative paths are
+    /// resolved against `base_dir`.
+    pub fn build(self, base_dir: &Path) -> Result<Scene, SceneError> {
+        let mut textures = HashMap::new();
+        // checkers may refer to other textures, so each one is built on
+        // first use and cached
+        for name in self.textures.keys() {
+            resolve_texture(name, &self.textures, &mut textures, base_dir, &mut vec![])?;
+        }
+
+        let materials = self
+            .materials
+            .iter()
+            .map(|(name, description)| {
+                let material =
+                    build_material(description, &self.textures, &mut textures, base_dir)?;
+                Ok((name.clone(), material))
+            })
+            .collect::<Result<HashMap<_, _>, SceneError>>()?;
+
+        // the contents of every file the scene loads count as part of it
+        let mut names: Vec<_> = textures.keys().collect();
+        names.sort();
+        let mut assets: Vec<u64> = names
+            .into_iter()
+            .map(|name| textures[name].fingerprint())
+            .collect();
+
+        let mut world = HittableList { objects: vec![] };
+        let mut lights = HittableList { objects: vec![] };
+        for shape in &self.shapes {
+            let built = build_shape(shape, &materials, base_dir, &mut assets)?;
+            if shape.is_light(&materials) {
+                let shared: Arc<dyn Hittable + Send + Sync> = Arc::from(built);
+                lights.objects.push(Box::new(shared.clone()));
+                world.objects.push(Box::new(shared));
+            } else {
+                world.objects.push(built);
+            }
+        }
+
+        let mut render = self.render;
+        render.output = base_dir.join(render.output);
+
+        Ok(Scene {
+            camera: self.camera,
+            world,
+            lights,
+            render,
+            fingerprint: hash_values(self.fingerprint, &assets),
+        })
+    }
+}
+
+impl ShapeDescription {
+    fn is_light(&self, materials: &HashMap<String, Material>) -> bool {
+        let material = match self {
+            ShapeDescription::Sphere { material, .. }
+            | ShapeDescription::Quad { material, .. }
+            | ShapeDescription::Triangle { material, .. }
+            | ShapeDescription::Box { material, .. } => material,
+            ShapeDescription::Mesh { .. } => return false,
+        };
+        matches!(materials.get(material), Some(Material::DiffuseLight { .. }))
+    }
+}
+
+fn resolve_texture(
+    name: &str,
+    descriptions: &HashMap<String, TextureDescription>,
+    built: &mut HashMap<String, Texture>,
+    base_dir: &Path,
+    resolving: &mut Vec<String>,
+) -> Result<Texture, SceneError> {
+    if let Some(texture) = built.get(name) {
+        return Ok(texture.clone());
+    }
+    if resolving.iter().any(|outer| outer == name) {
+        return Err(SceneError::TextureCycle(name.to_string()));
+    }
+    let description = descriptions
+        .get(name)
+        .ok_or_else(|| SceneError::UnknownTexture(name.to_string()))?;
+
+    let texture = match description {
+        TextureDescription::Solid { color } => Texture::Solid(*color),
+        TextureDescription::Checker { scale, even, odd } => {
+            resolving.push(name.to_string());
+            let even = texture_ref(even, descriptions, built, base_dir, resolving)?;
+            let odd = texture_ref(odd, descriptions, built, base_dir, resolving)?;
+            resolving.pop();
+            Texture::checker(*scale, even, odd)
+        }
+        TextureDescription::Image { path } => {
+            let path = base_dir.join(path);
+            let image =
+                ImageTexture::load(&path).map_err(|source| SceneError::Image { path, source })?;
+            Texture::Image(Arc::new(image))
+        }
+        TextureDescription::Noise {
+            kind,
+            scale,
+            seed,
+            color,
+        } => {
+            let kind = match kind {
+                NoiseKindDescription::Perlin => NoiseKind::Perlin,
+                NoiseKindDescription::Turbulence => NoiseKind::Turbulence,
+                NoiseKindDescription::Marble => NoiseKind::Marble,
+            };
+            let mut noise = NoiseTexture::new(kind, *scale, *seed);
+            noise.color = *color;
+            Texture::Noise(noise)
+        }
+    };
+
+    built.insert(name.to_string(), texture.clone());
+    Ok(texture)
+}
+
+fn texture_ref(
+    texture: &TextureRef,
+    descriptions: &HashMap<String, TextureDescription>,
+    built: &mut HashMap<String, Texture>,
+    base_dir: &Path,
+    resolving: &mut Vec<String>,
+) -> Result<Texture, SceneError> {
+    match texture {
+        TextureRef::Color(color) => Ok(Texture::Solid(*color)),
+        TextureRef::Named(name) => resolve_texture(name, descriptions, built, base_dir, resolving),
+    }
+}
+
+fn build_material(
+    description: &MaterialDescription,
+    textures: &HashMap<String, TextureDescription>,
+    built: &mut HashMap<String, Texture>,
+    base_dir: &Path,
+) -> Result<Material, SceneError> {
+    Ok(match description {
+        MaterialDescription::Lambertian { albedo } => Material::Lambertian {
+            albedo: texture_ref(albedo, textures, built, base_dir, &mut vec![])?,
+        },
+        MaterialDescription::Metal { albedo, fuzz } => Material::Metal {
+            albedo: texture_ref(albedo, textures, built, base_dir, &mut vec![])?,
+            fuzz: *fuzz,
+        },
+        MaterialDescription::Dielectric { refractive_index } => Material::Dielectric {
+            refractive_index: *refractive_index,
+        },
+        MaterialDescription::DiffuseLight { emit } => Material::DiffuseLight { emit: *emit },
+    })
+}
+
+fn build_shape(
+    shape: &ShapeDescription,
+    materials: &HashMap<String, Material>,
+    base_dir: &Path,
+    assets: &mut Vec<u64>,
+) -> Result<Box<dyn Hittable + Send + Sync>, SceneError> {
+    let material = |name: &str| {
+        materials
+            .get(name)
+            .cloned()
+            .ok_or_else(|| SceneError::UnknownMaterial(name.to_string()))
+    };
+
+    Ok(match shape {
+        ShapeDescription::Sphere {
+            center,
+            radius,
+            material: name,
+        } => Box::new(Sphere {
+            center: *center,
+            radius: *radius,
+            material: material(name)?,
+        }),
+        ShapeDescription::Quad {
+            q,
+            u,
+            v,
+            material: name,
+        } => Box::new(Quad::new(*q, *u, *v, material(name)?)),
+        ShapeDescription::Triangle {
+            a,
+            b,
+            c,
+            material: name,
+        } => Box::new(Triangle {
+            a: *a,
+            b: *b,
+            c: *c,
+            material: material(name)?,
+        }),
+        ShapeDescription::Box {
+            min,
+            max,
+            material: name,
+        } => Box::new(cuboid(*min, *max, material(name)?)),
+        ShapeDescription::Mesh {
+            path,
+            material: name,
+            scale,
+            translate,
+        } => {
+            let default_material = match name {
+                Some(name) => material(name)?,
+                None => Material::Lambertian {
+                    albedo: Texture::Solid(DVec3::splat(0.5)),
+                },
+            };
+            let mut data =
+                load_obj_data(base_dir.join(path), default_material).map_err(SceneError::Mesh)?;
+            assets.push(data.fingerprint());
+            data.transform(DAffine3::from_scale_rotation_translation(
+                DVec3::splat(*scale),
+                Default::default(),
+                *translate,
+            ));
+            Box::new(TriangleMesh::new(data))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aov::Aov,
+        ray::Ray,
+        tonemap::{ToneCurve, ToneMap},
+    };
+
+    const SCENE: &str = r#"
+        [camera]
+        image_width = 64
+        aspect_ratio = 1.0
+        samples_per_pixel = 8
+        look_from = [0.0, 0.0, 5.0]
+        background = { solid = [0.0, 0.0, 0.0] }
+        seed = 3
+        aovs = ["depth", "normal"]
+        tone_map = { curve = "agx", exposure = 0.5 }
+
+        [render]
+        output = "renders/test.png"
+
+        [textures.checker]
+        type = "checker"
+        scale = 0.5
+        even = [1.0, 1.0, 1.0]
+        odd = "marble"
+
+        [textures.marble]
+        type = "noise"
+        kind = "marble"
+        scale = 4.0
+
+        [materials.ground]
+        type = "lambertian"
+        albedo = "checker"
+
+        [materials.mirror]
+        type = "metal"
+        albedo = [0.8, 0.8, 0.8]
+
+        [materials.glass]
+        type = "dielectric"
+        refractive_index = 1.5
+
+        [materials.lamp]
+        type = "diffuse_light"
+        emit = [4.0, 4.0, 4.0]
+
+        [[shapes]]
+        type = "sphere"
+        center = [0.0, 0.0, 0.0]
+        radius = 1.0
+        material = "glass"
+
+        [[shapes]]
+        type = "quad"
+        q = [-1.0, 2.0, -1.0]
+        u = [2.0, 0.0, 0.0]
+        v = [0.0, 0.0, 2.0]
+        material = "lamp"
+
+        [[shapes]]
+        type = "triangle"
+        a = [0.0, 0.0, -3.0]
+        b = [1.0, 0.0, -3.0]
+        c = [0.0, 1.0, -3.0]
+        material = "mirror"
+
+        [[shapes]]
+        type = "box"
+        min = [-5.0, -2.0, -5.0]
+        max = [5.0, -1.5, 5.0]
+        material = "ground"
+    "#;
+
+    #[test]
+    fn test_build_scene() {
+        let scene = SceneDescription::parse(SCENE)
+            .unwrap()
+            .build(Path::new("scenes"))
+            .unwrap();
+
+        assert_eq!(scene.camera.image_width, Some(64));
+        assert_eq!(scene.camera.look_from, Some(DVec3::new(0., 0., 5.)));
+        assert_eq!(scene.camera.seed, Some(3));
+        assert_eq!(scene.camera.aovs, Some(vec![Aov::Depth, Aov::Normal]));
+        assert_eq!(
+            scene.camera.tone_map,
+            Some(ToneMap {
+                curve: ToneCurve::Agx,
+                exposure: 0.5,
+                ..ToneMap::default()
+            })
+        );
+        assert_eq!(scene.world.objects.len(), 4);
+        assert_eq!(scene.lights.objects.len(), 1);
+        assert_eq!(scene.render.output, Path::new("scenes/renders/test.png"));
+
+        let ray = Ray::new(DVec3::new(0., 0., 5.), DVec3::new(0., 0., -1.));
+        let hit = scene.world.hit(&ray, 0.001..f64::INFINITY).unwrap();
+        assert_eq!(hit.t, 4.0);
+        assert!(matches!(hit.material, Material::Dielectric { .. }));
+    }
+
+    #[test]
+    fn test_bundled_scenes_load() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+        for name in ["cornell-box.toml", "textures.toml"] {
+            let scene = load_scene(dir.join(name)).unwrap();
+            assert!(!scene.world.objects.is_empty(), "{} is empty", name);
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_covers_meshes() {
+        let dir = std::env::temp_dir().join(format!("aurora-fingerprint-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = r#"
+            [[shapes]]
+            type = "mesh"
+            path = "triangle.obj"
+        "#;
+        let fingerprint = |obj: &str| {
+            fs::write(dir.join("triangle.obj"), obj).unwrap();
+            let description = SceneDescription::parse(source).unwrap();
+            description.build(&dir).unwrap().fingerprint
+        };
+        let triangle = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+        let moved = "v 0 0 0\nv 2 0 0\nv 0 1 0\nf 1 2 3\n";
+        let (a, b, c) = (
+            fingerprint(triangle),
+            fingerprint(moved),
+            fingerprint(triangle),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        assert_ne!(a, b);
+        assert_eq!(a, c);
+    }
+
+    #[test]
+    fn test_unknown_material() {
+        let source = r#"
+            [[shapes]]
+            type = "sphere"
+            center = [0.0, 0.0, 0.0]
+            radius = 1.0
+            material = "missing"
+        "#;
+        let result = SceneDescription::parse(source)
+            .unwrap()
+            .build(Path::new(""));
+        assert!(matches!(result, Err(SceneError::UnknownMaterial(name)) if name == "missing"));
+    }
+
+    #[test]
+    fn test_recursive_texture() {
+        let source = r#"
+            [textures.loop]
+            type = "checker"
+            scale = 1.0
+            even = "loop"
+            odd = [0.0, 0.0, 0.0]
+        "#;
+        let result = SceneDescription::parse(source)
+            .unwrap()
+            .build(Path::new(""));
+        assert!(matches!(result, Err(SceneError::TextureCycle(name)) if name == "loop"));
+
+        let source = r#"
+            [textures.a]
+            type = "checker"
+            scale = 1.0
+            even = "b"
+            odd = "b"
+
+            [textures.b]
+            type = "checker"
+            scale = 1.0
+            even = [0.0, 0.0, 0.0]
+            odd = "a"
+        "#;
+        let result = SceneDescription::parse(source)
+            .unwrap()
+            .build(Path::new(""));
+        assert!(matches!(result, Err(SceneError::TextureCycle(_))));
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        let source = "[camera]\nimage_widht = 10\n";
+        assert!(matches!(
+            SceneDescription::parse(source),
+            Err(SceneError::Parse(_))
+        ));
+        let source = "[camera]\nfilter = \"tent\"\nfilter_radius = 0.0\n";
+        assert!(matches!(
+            SceneDescription::parse(source),
+            Err(SceneError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,243 @@
+use std::{ops::Range, sync::Arc};
+
+use glam::{DAffine3, DVec2, DVec3};
+
+use crate::{
+    aabb::Aabb,
+    bvh::BvhNode,
+    fastrand::hash_values,
+    hittable::{HitRecord, Hittable, HittableList},
+    material::Material,
+    ray::Ray,
+    shapes::triangle::intersect_triangle,
+};
+
+/// One corner of a mesh face, as indices into the `MeshData` arrays.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MeshVertex {
+    pub position: usize,
+    pub normal: Option<usize>,
+    pub texcoord: Option<usize>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MeshFace {
+    pub vertices: [MeshVertex; 3],
+    /// Index into `MeshData::materials`.
+    pub material: usize,
+}
+
+/// Vertex attributes shared between all triangles of a mesh.
+#[derive(Clone, Default)]
+pub struct MeshData {
+    pub positions: Vec<DVec3>,
+    pub normals: Vec<DVec3>,
+    pub texcoords: Vec<DVec2>,
+    pub faces: Vec<MeshFace>,
+    pub materials: Vec<Material>,
+}
+
+impl MeshData {
+    /// A hash of the vertices, faces and materials, so scenes can tell when
+    /// a mesh file has changed.
+    pub fn fingerprint(&self) -> u64 {
+        let vectors = self.positions.iter().chain(&self.normals);
+        let texcoords = self
+            .texcoords
+            .iter()
+            .flat_map(|texcoord| texcoord.to_array());
+        let mut values: Vec<u64> = vectors
+            .flat_map(|vector| vector.to_array())
+            .chain(texcoords)
+            .map(f64::to_bits)
+            .collect();
+        let index = |index: Option<usize>| index.map_or(u64::MAX, |index| index as u64);
+        for face in &self.faces {
+            for vertex in face.vertices {
+                values.push(vertex.position as u64);
+                values.push(index(vertex.normal));
+                values.push(index(vertex.texcoord));
+            }
+            values.push(face.material as u64);
+        }
+        values.extend(self.materials.iter().map(Material::fingerprint));
+        let counts = [
+            self.positions.len(),
+            self.normals.len(),
+            self.texcoords.len(),
+        ];
+        hash_values(hash_values(0, &counts.map(|count| count as u64)), &values)
+    }
+
+    /// Moves every vertex by `transform`, keeping normals perpendicular to
+    /// the transformed surface.
+    pub fn transform(&mut self, transform: DAffine3) {
+        let normal_matrix = transform.matrix3.inverse().transpose();
+        for position in &mut self.positions {
+            *position = transform.transform_point3(*position);
+        }
+        for normal in &mut self.normals {
+            *normal = (normal_matrix * *normal).normalize_or_zero();
+        }
+    }
+}
+
+/// A triangle mesh with shared vertices.
+///
+/// Triangles whose vertices all have normals are shaded with the normals
+/// interpolated across the face, the rest with their flat face normal.
+pub struct TriangleMesh {
+    bvh: BvhNode,
+    face_count: usize,
+}
+
+impl TriangleMesh {
+    pub fn new(data: MeshData) -> Self {
+        let face_count = data.faces.len();
+        let data = Arc::new(data);
+        let triangles = HittableList {
+            objects: (0..face_count)
+                .map(|face| {
+                    Box::new(MeshTriangle {
+                        mesh: Arc::clone(&data),
+                        face,
+                    }) as Box<dyn Hittable + Send + Sync>
+                })
+                .collect(),
+        };
+
+        Self {
+            bvh: BvhNode::new(triangles),
+            face_count,
+        }
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.face_count
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord<'_>> {
+        self.bvh.hit(ray, interval)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounding_box()
+    }
+}
+
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    face: usize,
+}
+
+impl MeshTriangle {
+    fn corners(&self) -> [DVec3; 3] {
+        let face = &self.mesh.faces[self.face];
+        face.vertices
+            .map(|vertex| self.mesh.positions[vertex.position])
+    }
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord<'_>> {
+        let [a, b, c] = self.corners();
+        let (t, b1, b2) = intersect_triangle(ray, a, b, c)?;
+        if !interval.contains(&t) {
+            return None;
+        }
+
+        let face = &self.mesh.faces[self.face];
+        let weights = [1.0 - b1 - b2, b1, b2];
+        let material = &self.mesh.materials[face.material];
+        let geometric_normal = (b - a).cross(c - a).normalize();
+        let mut hit_record = HitRecord::new(ray.at(t), geometric_normal, t, ray, material);
+
+        let normals = face.vertices.map(|vertex| vertex.normal);
+        if let [Some(n0), Some(n1), Some(n2)] = normals {
+            let shading_normal = (weights[0] * self.mesh.normals[n0]
+                + weights[1] * self.mesh.normals[n1]
+                + weights[2] * self.mesh.normals[n2])
+                .normalize_or_zero();
+            if shading_normal != DVec3::ZERO {
+                // keep the smooth normal on the same side as the face normal
+                // so front/back stays consistent with the geometry
+                let oriented = if shading_normal.dot(geometric_normal) < 0.0 {
+                    -shading_normal
+                } else {
+                    shading_normal
+                };
+                hit_record.outward_normal = if hit_record.front_face {
+                    oriented
+                } else {
+                    -oriented
+                };
+            }
+        }
+
+        let texcoords = face.vertices.map(|vertex| vertex.texcoord);
+        let (u, v) = if let [Some(t0), Some(t1), Some(t2)] = texcoords {
+            let uv = weights[0] * self.mesh.texcoords[t0]
+                + weights[1] * self.mesh.texcoords[t1]
+                + weights[2] * self.mesh.texcoords[t2];
+            (uv.x, uv.y)
+        } else {
+            (b1, b2)
+        };
+
+        Some(hit_record.with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [a, b, c] = self.corners();
+        Aabb::new(a, b).union(&Aabb::new(c, c)).pad(1e-4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::Texture;
+
+    fn vertex(position: usize) -> MeshVertex {
+        MeshVertex {
+            position,
+            normal: Some(position),
+            texcoord: None,
+        }
+    }
+
+    #[test]
+    fn test_smooth_normal_interpolation() {
+        let data = MeshData {
+            positions: vec![DVec3::ZERO, DVec3::X, DVec3::Y],
+            normals: vec![
+                DVec3::new(-1., 0., 1.).normalize(),
+                DVec3::new(1., 0., 1.).normalize(),
+                DVec3::Z,
+            ],
+            texcoords: vec![],
+            faces: vec![MeshFace {
+                vertices: [vertex(0), vertex(1), vertex(2)],
+                material: 0,
+            }],
+            materials: vec![Material::Lambertian {
+                albedo: Texture::Solid(DVec3::ONE),
+            }],
+        };
+        let mesh = TriangleMesh::new(data);
+
+        // halfway between the first two vertices the x components cancel out
+        let ray = Ray::new(DVec3::new(0.5, 0., 1.), DVec3::new(0., 0., -1.));
+        let hit = mesh.hit(&ray, 0.0..f64::INFINITY).unwrap();
+        assert!(hit.front_face);
+        assert!((hit.outward_normal - DVec3::Z).length() < 1e-9);
+
+        // from behind the shading normal is flipped along with the face
+        let ray = Ray::new(DVec3::new(0.5, 0., -1.), DVec3::Z);
+        let hit = mesh.hit(&ray, 0.0..f64::INFINITY).unwrap();
+        assert!(!hit.front_face);
+        assert!((hit.outward_normal + DVec3::Z).length() < 1e-9);
+    }
+}
@@ -0,0 +1,239 @@
+use std::ops::Range;
+
+use glam::DVec3;
+
+use crate::{
+    aabb::Aabb,
+    fastrand::random_f64,
+    hittable::{HitRecord, Hittable, HittableList},
+    material::Material,
+    ray::Ray,
+};
+
+/// A parallelogram with corner `q` and edges `u` and `v`.
+/// The front face is the side `u x v` points towards.
+pub struct Quad {
+    q: DVec3,
+    u: DVec3,
+    v: DVec3,
+    material: Material,
+    normal: DVec3,
+    // plane is normal . p = d
+    d: f64,
+    // n / (n . n), used to turn a planar point into (alpha, beta)
+    w: DVec3,
+    area: f64,
+}
+
+impl Quad {
+    pub fn new(q: DVec3, u: DVec3, v: DVec3, material: Material) -> Self {
+        let n = u.cross(v);
+        let normal = n.normalize();
+        let d = normal.dot(q);
+        let w = n / n.dot(n);
+        let area = n.length();
+
+        Self {
+            q,
+            u,
+            v,
+            material,
+            normal,
+            d,
+            w,
+            area,
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord<'_>> {
+        let denom = self.normal.dot(ray.direction);
+
+        // ray is parallel to the plane
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = (self.d - self.normal.dot(ray.origin)) / denom;
+        if !interval.contains(&t) {
+            return None;
+        }
+
+        // express the hit point in the plane's (u, v) basis
+        let point = ray.at(t);
+        let planar = point - self.q;
+        let alpha = self.w.dot(planar.cross(self.v));
+        let beta = self.w.dot(self.u.cross(planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        Some(HitRecord::new(point, self.normal, t, ray, &self.material).with_uv(alpha, beta))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let diagonal_1 = Aabb::new(self.q, self.q + self.u + self.v);
+        let diagonal_2 = Aabb::new(self.q + self.u, self.q + self.v);
+        diagonal_1.union(&diagonal_2).pad(1e-4)
+    }
+
+    /// Uniform over the quad's area, converted to solid angle.
+    fn pdf_value(&self, origin: DVec3, direction: DVec3) -> f64 {
+        let Some(hit) = self.hit(&Ray::new(origin, direction), 0.001..f64::INFINITY) else {
+            return 0.0;
+        };
+
+        let distance_squared = hit.t * hit.t * direction.length_squared();
+        let cosine = (direction.dot(self.normal) / direction.length()).abs();
+        if cosine < 1e-8 {
+            return 0.0;
+        }
+
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: DVec3) -> DVec3 {
+        let point = self.q + random_f64() * self.u + random_f64() * self.v;
+        point - origin
+    }
+}
+
+/// Returns the six outward facing sides of the box with opposite corners
+/// `a` and `b`.
+pub fn cuboid(a: DVec3, b: DVec3, material: Material) -> HittableList {
+    let min = a.min(b);
+    let max = a.max(b);
+
+    let dx = DVec3::new(max.x - min.x, 0.0, 0.0);
+    let dy = DVec3::new(0.0, max.y - min.y, 0.0);
+    let dz = DVec3::new(0.0, 0.0, max.z - min.z);
+
+    let sides: [Box<dyn Hittable + Send + Sync>; 6] = [
+        // front
+        Box::new(Quad::new(
+            DVec3::new(min.x, min.y, max.z),
+            dx,
+            dy,
+            material.clone(),
+        )),
+        // right
+        Box::new(Quad::new(
+            DVec3::new(max.x, min.y, max.z),
+            -dz,
+            dy,
+            material.clone(),
+        )),
+        // back
+        Box::new(Quad::new(
+            DVec3::new(max.x, min.y, min.z),
+            -dx,
+            dy,
+            material.clone(),
+        )),
+        // left
+        Box::new(Quad::new(
+            DVec3::new(min.x, min.y, min.z),
+            dz,
+            dy,
+            material.clone(),
+        )),
+        // top
+        Box::new(Quad::new(
+            DVec3::new(min.x, max.y, max.z),
+            dx,
+            -dz,
+            material.clone(),
+        )),
+        // bottom
+        Box::new(Quad::new(DVec3::new(min.x, min.y, min.z), dx, dz, material)),
+    ];
+
+    HittableList {
+        objects: sides.into_iter().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::Texture;
+
+    fn unit_quad() -> Quad {
+        Quad::new(
+            DVec3::ZERO,
+            DVec3::X,
+            DVec3::Y,
+            Material::Lambertian {
+                albedo: Texture::Solid(DVec3::ONE),
+            },
+        )
+    }
+
+    #[test]
+    fn test_hit_inside() {
+        let ray = Ray::new(DVec3::new(0.25, 0.75, 1.), DVec3::new(0., 0., -1.));
+        let shape = unit_quad();
+        let hit = shape.hit(&ray, 0.0..f64::INFINITY).unwrap();
+        assert_eq!(hit.t, 1.);
+        assert_eq!(hit.point, DVec3::new(0.25, 0.75, 0.));
+        assert_eq!((hit.u, hit.v), (0.25, 0.75));
+        assert!(hit.front_face);
+    }
+
+    #[test]
+    fn test_hit_from_behind() {
+        let ray = Ray::new(DVec3::new(0.5, 0.5, -1.), DVec3::Z);
+        let shape = unit_quad();
+        let hit = shape.hit(&ray, 0.0..f64::INFINITY).unwrap();
+        assert!(!hit.front_face);
+        assert_eq!(hit.outward_normal, -DVec3::Z);
+    }
+
+    #[test]
+    fn test_miss() {
+        let outside = Ray::new(DVec3::new(1.5, 0.5, 1.), DVec3::new(0., 0., -1.));
+        let parallel = Ray::new(DVec3::new(0.5, 0.5, 1.), DVec3::X);
+        assert!(unit_quad().hit(&outside, 0.0..f64::INFINITY).is_none());
+        assert!(unit_quad().hit(&parallel, 0.0..f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_pdf_value() {
+        let shape = unit_quad();
+        let origin = DVec3::new(0.5, 0.5, 1.);
+        assert!((shape.pdf_value(origin, -DVec3::Z) - 1.0).abs() < 1e-12);
+        // twice as far away covers a quarter of the solid angle
+        let far = DVec3::new(0.5, 0.5, 2.);
+        assert!((shape.pdf_value(far, -DVec3::Z) - 4.0).abs() < 1e-12);
+        assert_eq!(shape.pdf_value(origin, DVec3::Z), 0.0);
+
+        for _ in 0..100 {
+            let direction = shape.random(origin);
+            assert!(shape.pdf_value(origin, direction) > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_cuboid_faces_outward() {
+        let material = Material::Lambertian {
+            albedo: Texture::Solid(DVec3::ONE),
+        };
+        let cuboid = cuboid(DVec3::splat(1.), DVec3::splat(-1.), material);
+        assert_eq!(cuboid.objects.len(), 6);
+
+        for direction in [
+            DVec3::X,
+            DVec3::Y,
+            DVec3::Z,
+            -DVec3::X,
+            -DVec3::Y,
+            -DVec3::Z,
+        ] {
+            let ray = Ray::new(direction * 5.0, -direction);
+            let hit = cuboid.hit(&ray, 0.0..f64::INFINITY).unwrap();
+            assert!(hit.front_face);
+            assert_eq!(hit.t, 4.0);
+        }
+    }
+}
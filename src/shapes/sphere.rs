@@ -1,8 +1,10 @@
-use std::ops::Range;
+use std::{f64::consts::PI, ops::Range};
 
 use glam::DVec3;
 
 use crate::{
+    aabb::Aabb,
+    fastrand::random_f64,
     hittable::{HitRecord, Hittable},
     material::Material,
     ray::Ray,
@@ -15,7 +17,7 @@ pub struct Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord<'_>> {
         let oc = self.center - ray.origin;
         let a = ray.direction.dot(ray.direction);
         let h = ray.direction.dot(oc);
@@ -38,13 +40,101 @@ impl Hittable for Sphere {
         let t = root;
         let point = ray.at(t);
         let outward_normal = (point - self.center) / self.radius;
+        let (u, v) = sphere_uv(outward_normal);
 
-        Some(HitRecord::new(
-            point,
-            outward_normal,
-            t,
-            ray,
-            self.material.clone(),
-        ))
+        Some(HitRecord::new(point, outward_normal, t, ray, &self.material).with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let radius = DVec3::splat(self.radius.abs());
+        Aabb::new(self.center - radius, self.center + radius)
+    }
+
+    /// Uniform over the cone of directions the sphere covers as seen from
+    /// `origin`.
+    fn pdf_value(&self, origin: DVec3, direction: DVec3) -> f64 {
+        let ray = Ray::new(origin, direction);
+        if self.hit(&ray, 0.001..f64::INFINITY).is_none() {
+            return 0.0;
+        }
+
+        let distance_squared = (self.center - origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            // inside the sphere there is no cone to sample
+            return 0.0;
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: DVec3) -> DVec3 {
+        let direction = self.center - origin;
+        let distance_squared = direction.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return direction;
+        }
+
+        // sample the cone around the z axis, then rotate it onto `direction`
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let z = 1.0 + random_f64() * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * random_f64();
+        let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+        let local = DVec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z);
+
+        let w = direction / distance_squared.sqrt();
+        let (u, v) = w.any_orthonormal_pair();
+        local.x * u + local.y * v + local.z * w
+    }
+}
+
+/// Maps a point on the unit sphere to (u, v): u is the angle around the
+/// y axis starting from -x, v the angle from the bottom (-y) to the top (+y),
+/// both scaled to [0, 1].
+fn sphere_uv(point: DVec3) -> (f64, f64) {
+    let theta = (-point.y).acos();
+    let phi = (-point.z).atan2(point.x) + PI;
+
+    (phi / (2.0 * PI), theta / PI)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sphere_uv() {
+        let close = |(u, v): (f64, f64), expected: (f64, f64)| {
+            (u - expected.0).abs() < 1e-12 && (v - expected.1).abs() < 1e-12
+        };
+        assert!(close(sphere_uv(DVec3::X), (0.5, 0.5)));
+        assert!(close(sphere_uv(DVec3::Y), (0.5, 1.0)));
+        assert!(close(sphere_uv(DVec3::new(-1., 0., 0.)), (0.0, 0.5)));
+        assert!(close(sphere_uv(DVec3::Z), (0.25, 0.5)));
+        assert!(close(sphere_uv(DVec3::new(0., 0., -1.)), (0.75, 0.5)));
+    }
+
+    #[test]
+    fn test_sampled_directions_hit_sphere() {
+        let sphere = Sphere {
+            center: DVec3::new(0., 0., -5.),
+            radius: 1.0,
+            material: Material::Dielectric {
+                refractive_index: 1.5,
+            },
+        };
+        // the sphere covers 2 pi (1 - cos(theta_max)) steradians
+        let cos_theta_max = (1.0 - 1.0 / 25.0_f64).sqrt();
+        let expected_pdf = 1.0 / (2.0 * PI * (1.0 - cos_theta_max));
+
+        for _ in 0..100 {
+            let direction = sphere.random(DVec3::ZERO);
+            let pdf = sphere.pdf_value(DVec3::ZERO, direction);
+            assert!((pdf - expected_pdf).abs() < 1e-9);
+        }
+        assert_eq!(sphere.pdf_value(DVec3::ZERO, DVec3::Z), 0.0);
     }
 }
@@ -0,0 +1,147 @@
+use std::ops::Range;
+
+use glam::DVec3;
+
+use crate::{
+    aabb::Aabb,
+    fastrand::random_f64,
+    hittable::{HitRecord, Hittable},
+    material::Material,
+    ray::Ray,
+};
+
+/// A single triangle. The front face is the side the vertices `a`, `b`, `c`
+/// appear counter-clockwise from.
+pub struct Triangle {
+    pub a: DVec3,
+    pub b: DVec3,
+    pub c: DVec3,
+    pub material: Material,
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord<'_>> {
+        let (t, b1, b2) = intersect_triangle(ray, self.a, self.b, self.c)?;
+        if !interval.contains(&t) {
+            return None;
+        }
+
+        let outward_normal = (self.b - self.a).cross(self.c - self.a).normalize();
+        Some(HitRecord::new(ray.at(t), outward_normal, t, ray, &self.material).with_uv(b1, b2))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.a, self.b)
+            .union(&Aabb::new(self.c, self.c))
+            .pad(1e-4)
+    }
+
+    /// Uniform over the triangle's area, converted to solid angle.
+    fn pdf_value(&self, origin: DVec3, direction: DVec3) -> f64 {
+        let Some(hit) = self.hit(&Ray::new(origin, direction), 0.001..f64::INFINITY) else {
+            return 0.0;
+        };
+
+        let n = (self.b - self.a).cross(self.c - self.a);
+        let area = 0.5 * n.length();
+        let distance_squared = hit.t * hit.t * direction.length_squared();
+        let cosine = (direction.dot(n) / (direction.length() * n.length())).abs();
+        if cosine < 1e-8 {
+            return 0.0;
+        }
+
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, origin: DVec3) -> DVec3 {
+        // folding the unit square onto the triangle keeps the density uniform
+        let (mut b1, mut b2) = (random_f64(), random_f64());
+        if b1 + b2 > 1.0 {
+            b1 = 1.0 - b1;
+            b2 = 1.0 - b2;
+        }
+        let point = self.a + b1 * (self.b - self.a) + b2 * (self.c - self.a);
+        point - origin
+    }
+}
+
+/// Möller–Trumbore ray/triangle intersection.
+/// Returns the ray parameter `t` and the barycentric weights of `b` and `c`
+/// (the weight of `a` is `1 - b1 - b2`).
+pub(crate) fn intersect_triangle(
+    ray: &Ray,
+    a: DVec3,
+    b: DVec3,
+    c: DVec3,
+) -> Option<(f64, f64, f64)> {
+    let edge_1 = b - a;
+    let edge_2 = c - a;
+    let p = ray.direction.cross(edge_2);
+    let det = edge_1.dot(p);
+
+    // ray is parallel to the triangle's plane
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let s = ray.origin - a;
+    let b1 = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+
+    let q = s.cross(edge_1);
+    let b2 = ray.direction.dot(q) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = edge_2.dot(q) * inv_det;
+    Some((t, b1, b2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::Texture;
+
+    fn triangle() -> Triangle {
+        Triangle {
+            a: DVec3::ZERO,
+            b: DVec3::X,
+            c: DVec3::Y,
+            material: Material::Lambertian {
+                albedo: Texture::Solid(DVec3::ONE),
+            },
+        }
+    }
+
+    #[test]
+    fn test_hit_barycentric() {
+        let ray = Ray::new(DVec3::new(0.25, 0.5, 2.), DVec3::new(0., 0., -1.));
+        let shape = triangle();
+        let hit = shape.hit(&ray, 0.0..f64::INFINITY).unwrap();
+        assert_eq!(hit.t, 2.);
+        assert_eq!((hit.u, hit.v), (0.25, 0.5));
+        assert_eq!(hit.outward_normal, DVec3::Z);
+        assert!(hit.front_face);
+    }
+
+    #[test]
+    fn test_back_face() {
+        let ray = Ray::new(DVec3::new(0.25, 0.25, -1.), DVec3::Z);
+        let shape = triangle();
+        let hit = shape.hit(&ray, 0.0..f64::INFINITY).unwrap();
+        assert!(!hit.front_face);
+        assert_eq!(hit.outward_normal, -DVec3::Z);
+    }
+
+    #[test]
+    fn test_miss() {
+        let outside = Ray::new(DVec3::new(0.75, 0.75, 1.), DVec3::new(0., 0., -1.));
+        let behind = Ray::new(DVec3::new(0.25, 0.25, 1.), DVec3::Z);
+        assert!(triangle().hit(&outside, 0.0..f64::INFINITY).is_none());
+        assert!(triangle().hit(&behind, 0.0..f64::INFINITY).is_none());
+    }
+}
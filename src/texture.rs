@@ -0,0 +1,129 @@
+pub mod image;
+pub mod noise;
+
+use std::sync::Arc;
+
+use glam::DVec3;
+
+use crate::{
+    fastrand::{hash_values, hash_vec, mix64},
+    texture::{image::ImageTexture, noise::NoiseTexture},
+};
+
+/// A colour that varies over a surface, looked up by the hit's surface
+/// coordinates (u, v) and/or its position in space.
+#[derive(Clone)]
+pub enum Texture {
+    /// The same colour everywhere.
+    Solid(DVec3),
+    /// Alternating 3D cubes of two textures, each `scale` units wide.
+    Checker {
+        scale: f64,
+        even: Arc<Texture>,
+        odd: Arc<Texture>,
+    },
+    /// A picture wrapped onto the surface with its (u, v) coordinates.
+    Image(Arc<ImageTexture>),
+    /// Procedural Perlin noise.
+    Noise(NoiseTexture),
+}
+
+impl Texture {
+    pub fn checker(scale: f64, even: Texture, odd: Texture) -> Self {
+        Texture::Checker {
+            scale,
+            even: Arc::new(even),
+            odd: Arc::new(odd),
+        }
+    }
+
+    pub fn value(&self, u: f64, v: f64, point: DVec3) -> DVec3 {
+        match self {
+            Texture::Solid(color) => *color,
+            Texture::Checker { scale, even, odd } => {
+                let cell = (point / *scale).floor();
+                let sum = cell.x as i64 + cell.y as i64 + cell.z as i64;
+                if sum.rem_euclid(2) == 0 {
+                    even.value(u, v, point)
+                } else {
+                    odd.value(u, v, point)
+                }
+            }
+            Texture::Image(image) => image.value(u, v),
+            Texture::Noise(noise) => noise.value(point),
+        }
+    }
+
+    /// A hash of the texture's parameters: textures that look the same get
+    /// the same value, in every run and on every machine.
+    pub fn fingerprint(&self) -> u64 {
+        match self {
+            Texture::Solid(color) => hash_vec(1, *color),
+            Texture::Checker { scale, even, odd } => {
+                mix64(hash_values(2, &[scale.to_bits(), even.fingerprint()]) ^ odd.fingerprint())
+            }
+            Texture::Image(image) => hash_values(3, &[image.fingerprint()]),
+            Texture::Noise(noise) => hash_values(
+                4,
+                &[
+                    noise.perlin.seed(),
+                    noise.scale.to_bits(),
+                    noise.kind as u64,
+                    hash_vec(0, noise.color),
+                ],
+            ),
+        }
+    }
+}
+
+impl From<DVec3> for Texture {
+    fn from(color: DVec3) -> Self {
+        Texture::Solid(color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::noise::NoiseKind;
+
+    #[test]
+    fn test_solid() {
+        let texture = Texture::from(DVec3::new(0.1, 0.2, 0.3));
+        assert_eq!(
+            texture.value(0.7, 0.2, DVec3::ONE),
+            DVec3::new(0.1, 0.2, 0.3)
+        );
+    }
+
+    #[test]
+    fn test_checker_alternates() {
+        let texture =
+            Texture::checker(1.0, Texture::Solid(DVec3::ONE), Texture::Solid(DVec3::ZERO));
+        assert_eq!(texture.value(0., 0., DVec3::splat(0.5)), DVec3::ONE);
+        assert_eq!(
+            texture.value(0., 0., DVec3::new(1.5, 0.5, 0.5)),
+            DVec3::ZERO
+        );
+        assert_eq!(texture.value(0., 0., DVec3::new(1.5, 1.5, 0.5)), DVec3::ONE);
+        assert_eq!(
+            texture.value(0., 0., DVec3::new(-0.5, 0.5, 0.5)),
+            DVec3::ZERO
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_identity() {
+        let image = || {
+            let pixels = vec![DVec3::X, DVec3::Y];
+            Texture::Image(Arc::new(ImageTexture::new(2, 1, pixels)))
+        };
+        let noise = |seed| Texture::Noise(NoiseTexture::new(NoiseKind::Marble, 4.0, seed));
+        assert_eq!(image().fingerprint(), image().fingerprint());
+        assert_eq!(noise(3).fingerprint(), noise(3).fingerprint());
+        assert_ne!(noise(3).fingerprint(), noise(4).fingerprint());
+
+        let other = Texture::Image(Arc::new(ImageTexture::new(2, 1, vec![DVec3::Y, DVec3::X])));
+        assert_ne!(image().fingerprint(), other.fingerprint());
+    }
+}
@@ -0,0 +1,113 @@
+use std::path::Path;
+
+use glam::DVec3;
+use image::ImageResult;
+
+use crate::fastrand::hash_values;
+
+/// Linear RGB pixels sampled by (u, v), where (0, 0) is the bottom left
+/// corner of the picture.
+pub struct ImageTexture {
+    width: u32,
+    height: u32,
+    pixels: Vec<DVec3>,
+    /// A hash of the size and pixels, worked out once up front.
+    fingerprint: u64,
+}
+
+impl ImageTexture {
+    /// Creates a texture from linear RGB pixels stored row by row, starting
+    /// at the top left.
+    pub fn new(width: u32, height: u32, pixels: Vec<DVec3>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width as usize * height as usize,
+            "pixel count must match the image size"
+        );
+        let values: Vec<u64> = pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_array().map(f64::to_bits))
+            .collect();
+        Self {
+            fingerprint: hash_values(hash_values(0, &[width as u64, height as u64]), &values),
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Loads a PNG or JPEG file. The stored sRGB colours are converted to
+    /// linear so they can be mixed with light.
+    pub fn load(path: impl AsRef<Path>) -> ImageResult<Self> {
+        let image = image::open(path)?.into_rgb32f();
+        let (width, height) = image.dimensions();
+        let pixels = image
+            .pixels()
+            .map(|p| DVec3::new(p[0] as f64, p[1] as f64, p[2] as f64).map(srgb_to_linear))
+            .collect();
+
+        Ok(Self::new(width, height, pixels))
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Equal for images of the same size and pixels, wherever they were
+    /// loaded.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Nearest pixel lookup. Coordinates outside [0, 1] are clamped to the
+    /// edge of the image.
+    pub fn value(&self, u: f64, v: f64) -> DVec3 {
+        if self.pixels.is_empty() {
+            // solid cyan makes missing textures easy to spot
+            return DVec3::new(0.0, 1.0, 1.0);
+        }
+
+        let u = u.clamp(0.0, 1.0);
+        // image rows go top to bottom, v goes bottom to top
+        let v = 1.0 - v.clamp(0.0, 1.0);
+        let x = ((u * self.width as f64) as u32).min(self.width - 1);
+        let y = ((v * self.height as f64) as u32).min(self.height - 1);
+
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+fn srgb_to_linear(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_orientation() {
+        // top row: red, green. bottom row: blue, white
+        let texture = ImageTexture::new(2, 2, vec![DVec3::X, DVec3::Y, DVec3::Z, DVec3::ONE]);
+        assert_eq!(texture.value(0.25, 0.75), DVec3::X);
+        assert_eq!(texture.value(0.75, 0.75), DVec3::Y);
+        assert_eq!(texture.value(0.25, 0.25), DVec3::Z);
+        assert_eq!(texture.value(1.0, 0.0), DVec3::ONE);
+        assert_eq!(texture.value(-3.0, 7.0), DVec3::X);
+    }
+
+    #[test]
+    fn test_srgb_to_linear() {
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-12);
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+    }
+}
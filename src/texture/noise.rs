@@ -0,0 +1,173 @@
+use std::sync::Arc;
+
+use glam::DVec3;
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+const POINT_COUNT: usize = 256;
+
+/// Gradient noise lattice. Seeded, so the same seed always produces the
+/// same pattern.
+pub struct Perlin {
+    seed: u64,
+    gradients: Vec<DVec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| {
+                DVec3::new(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                )
+                .normalize_or_zero()
+            })
+            .collect();
+
+        let mut permutation = || {
+            let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+            perm.shuffle(&mut rng);
+            perm
+        };
+        let perm_x = permutation();
+        let perm_y = permutation();
+        let perm_z = permutation();
+
+        Self {
+            seed,
+            gradients,
+            perm_x,
+            perm_y,
+            perm_z,
+        }
+    }
+
+    /// The seed the lattice was made from, which determines all of it.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Smoothly varying noise in roughly [-1, 1].
+    pub fn noise(&self, point: DVec3) -> f64 {
+        let cell = point.floor();
+        let local = point - cell;
+        let (i, j, k) = (cell.x as i64, cell.y as i64, cell.z as i64);
+
+        // Hermite smoothing hides the lattice
+        let smooth = local * local * (3.0 - 2.0 * local);
+
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let gradient = self.gradients[self.perm_x[wrap(i + di)]
+                        ^ self.perm_y[wrap(j + dj)]
+                        ^ self.perm_z[wrap(k + dk)]];
+                    let corner = DVec3::new(di as f64, dj as f64, dk as f64);
+                    let weight = corner * smooth + (1.0 - corner) * (1.0 - smooth);
+                    accum += weight.x * weight.y * weight.z * gradient.dot(local - corner);
+                }
+            }
+        }
+
+        accum
+    }
+
+    /// Sum of `depth` octaves of noise, each at double the frequency and half
+    /// the amplitude of the last.
+    pub fn turbulence(&self, point: DVec3, depth: u32) -> f64 {
+        let mut accum = 0.0;
+        let mut point = point;
+        let mut weight = 1.0;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(point);
+            weight *= 0.5;
+            point *= 2.0;
+        }
+
+        accum.abs()
+    }
+}
+
+fn wrap(index: i64) -> usize {
+    (index & (POINT_COUNT as i64 - 1)) as usize
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NoiseKind {
+    /// Plain noise, remapped from [-1, 1] to [0, 1].
+    Perlin,
+    /// Several octaves of noise, like clouds or smoke.
+    Turbulence,
+    /// Sine stripes along z, distorted by turbulence.
+    Marble,
+}
+
+/// Greyscale noise pattern tinted by `color`.
+#[derive(Clone)]
+pub struct NoiseTexture {
+    pub perlin: Arc<Perlin>,
+    /// Frequency of the pattern; larger values give finer detail.
+    pub scale: f64,
+    pub kind: NoiseKind,
+    pub color: DVec3,
+}
+
+impl NoiseTexture {
+    pub fn new(kind: NoiseKind, scale: f64, seed: u64) -> Self {
+        Self {
+            perlin: Arc::new(Perlin::new(seed)),
+            scale,
+            kind,
+            color: DVec3::ONE,
+        }
+    }
+
+    pub fn value(&self, point: DVec3) -> DVec3 {
+        let p = self.scale * point;
+        let intensity = match self.kind {
+            NoiseKind::Perlin => 0.5 * (1.0 + self.perlin.noise(p)),
+            NoiseKind::Turbulence => self.perlin.turbulence(p, 7),
+            NoiseKind::Marble => 0.5 * (1.0 + (p.z + 10.0 * self.perlin.turbulence(p, 7)).sin()),
+        };
+
+        intensity * self.color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise_is_seeded() {
+        let a = Perlin::new(7);
+        let b = Perlin::new(7);
+        let point = DVec3::new(1.3, -4.2, 0.7);
+        assert_eq!(a.noise(point), b.noise(point));
+    }
+
+    #[test]
+    fn test_noise_is_zero_on_lattice() {
+        let perlin = Perlin::new(1);
+        assert_eq!(perlin.noise(DVec3::new(3., -2., 5.)), 0.0);
+    }
+
+    #[test]
+    fn test_texture_range() {
+        for kind in [NoiseKind::Perlin, NoiseKind::Turbulence, NoiseKind::Marble] {
+            let texture = NoiseTexture::new(kind, 4.0, 3);
+            for i in 0..100 {
+                let point = DVec3::new(i as f64 * 0.37, i as f64 * -0.11, i as f64 * 0.05);
+                let value = texture.value(point);
+                assert!(value.x >= 0.0, "{:?} gave {}", kind, value);
+            }
+        }
+    }
+}
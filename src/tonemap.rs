@@ -0,0 +1,258 @@
+//! The display transform applied when a linear render is written to an 8
+//! or 16 bit image: exposure, a tone curve, the sRGB transfer function and
+//! optional dithering. Float formats skip it and keep the raw radiance.
+
+use glam::{DMat3, DVec3};
+use serde::{Deserialize, Serialize};
+
+use crate::fastrand::mix64;
+
+/// Compresses linear radiance into [0, 1].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneCurve {
+    /// Leaves values alone, so everything above 1 clips to white.
+    #[default]
+    Clamp,
+    /// `L / (1 + L)` on luminance. Never quite reaches white.
+    Reinhard,
+    /// Reinhard with `ToneMap::white_point` mapped to white.
+    ExtendedReinhard,
+    /// Narkowicz's fit of the ACES filmic curve, per channel.
+    Aces,
+    /// Troy Sobotka's AgX, which desaturates bright colours gracefully.
+    Agx,
+}
+
+impl ToneCurve {
+    pub const ALL: [ToneCurve; 5] = [
+        ToneCurve::Clamp,
+        ToneCurve::Reinhard,
+        ToneCurve::ExtendedReinhard,
+        ToneCurve::Aces,
+        ToneCurve::Agx,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneCurve::Clamp => "clamp",
+            ToneCurve::Reinhard => "reinhard",
+            ToneCurve::ExtendedReinhard => "extended_reinhard",
+            ToneCurve::Aces => "aces",
+            ToneCurve::Agx => "agx",
+        }
+    }
+
+    pub fn apply(self, color: DVec3, white_point: f64) -> DVec3 {
+        match self {
+            ToneCurve::Clamp => color,
+            ToneCurve::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneCurve::ExtendedReinhard => {
+                let white_sq = white_point * white_point;
+                scale_luminance(color, |l| l * (1.0 + l / white_sq) / (1.0 + l))
+            }
+            ToneCurve::Aces => {
+                let x = color.max(DVec3::ZERO) * 0.6;
+                (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+            }
+            ToneCurve::Agx => agx(color),
+        }
+        .clamp(DVec3::ZERO, DVec3::ONE)
+    }
+}
+
+/// Settings for turning linear radiance into display values.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToneMap {
+    /// Brightness change in stops: every +1 doubles the radiance.
+    pub exposure: f64,
+    pub curve: ToneCurve,
+    /// The luminance `ToneCurve::ExtendedReinhard` maps to white.
+    pub white_point: f64,
+    /// Adds up to one step of noise before quantising, which trades
+    /// banding in smooth gradients for fine grain.
+    pub dither: bool,
+}
+
+impl Default for ToneMap {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            curve: ToneCurve::Clamp,
+            white_point: 4.0,
+            dither: false,
+        }
+    }
+}
+
+impl ToneMap {
+    /// Exposure and the tone curve, giving linear values in [0, 1].
+    pub fn tone_map(&self, color: DVec3) -> DVec3 {
+        let exposed = color * self.exposure.exp2();
+        self.curve.apply(exposed, self.white_point)
+    }
+
+    /// The full transform to sRGB encoded values in [0, 1].
+    pub fn display(&self, color: DVec3) -> DVec3 {
+        let mapped = self.tone_map(color);
+        DVec3::new(
+            linear_to_srgb(mapped.x),
+            linear_to_srgb(mapped.y),
+            linear_to_srgb(mapped.z),
+        )
+    }
+
+    /// Quantises pixel `index` to integers in [0, `max`]. Dither noise is a
+    /// hash of the index, so the same image always encodes the same way.
+    pub fn quantize(&self, color: DVec3, index: usize, max: u32) -> [u32; 3] {
+        let display = self.display(color).to_array();
+        let levels = max as f64 + 1.0;
+        std::array::from_fn(|channel| {
+            let noise = if self.dither {
+                triangle_noise(mix64(((index as u64) << 2) | channel as u64))
+            } else {
+                0.0
+            };
+            (levels * display[channel] + noise).clamp(0.0, max as f64) as u32
+        })
+    }
+}
+
+/// The sRGB opto-electronic transfer function: a short linear toe, then a
+/// 1/2.4 power curve.
+pub fn linear_to_srgb(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92 * linear.max(0.0)
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Rec. 709 luminance of a linear colour.
+pub fn luminance(color: DVec3) -> f64 {
+    color.dot(DVec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Maps the luminance of `color` through `curve` and keeps its hue.
+fn scale_luminance(color: DVec3, curve: impl Fn(f64) -> f64) -> DVec3 {
+    let l = luminance(color);
+    if l <= 0.0 {
+        return DVec3::ZERO;
+    }
+    color * (curve(l) / l)
+}
+
+/// Noise in (-1, 1), denser around 0, from the sum of two uniform values.
+fn triangle_noise(hash: u64) -> f64 {
+    let uniform = |bits: u64| (bits & 0xffff_ffff) as f64 / 4_294_967_296.0;
+    uniform(hash) + uniform(hash >> 32) - 1.0
+}
+
+/// Minimal AgX: inset to the AgX working space, a log2 encoding over 16.5
+/// stops, the default contrast sigmoid, then back out to linear.
+fn agx(color: DVec3) -> DVec3 {
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+    let inset = DMat3::from_cols_array(&[
+        0.842479062253094,
+        0.0423282422610123,
+        0.0423756549057051,
+        0.0784335999999992,
+        0.878468636469772,
+        0.0784336,
+        0.0792237451477643,
+        0.0791661274605434,
+        0.879142973793104,
+    ]);
+    let outset = DMat3::from_cols_array(&[
+        1.19687900512017,
+        -0.0528968517574562,
+        -0.0529716355144438,
+        -0.0980208811401368,
+        1.15190312990417,
+        -0.0980434501171241,
+        -0.0990297440797205,
+        -0.0989611768448433,
+        1.15107367264116,
+    ]);
+
+    let encoded = (inset * color.max(DVec3::splat(1e-10)))
+        .to_array()
+        .map(|c| {
+            let x = (c.log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+            let x2 = x * x;
+            let x4 = x2 * x2;
+            15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+                - 0.00232
+        });
+    (outset * DVec3::from_array(encoded))
+        .max(DVec3::ZERO)
+        .powf(2.2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_srgb_transfer() {
+        assert_eq!(linear_to_srgb(0.0), 0.0);
+        assert!((linear_to_srgb(1.0) - 1.0).abs() < 1e-12);
+        assert!((linear_to_srgb(0.5) - 0.735357).abs() < 1e-6);
+        // the two pieces meet at the threshold
+        let toe = 12.92 * 0.0031308;
+        assert!((linear_to_srgb(0.0031309) - toe).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_curves_stay_in_range_and_increase() {
+        for curve in ToneCurve::ALL {
+            let mut previous = -1.0;
+            for i in 0..=64 {
+                let value = curve.apply(DVec3::splat(i as f64 / 4.0), 4.0).x;
+                assert!((0.0..=1.0).contains(&value), "{:?} gave {}", curve, value);
+                assert!(value >= previous, "{:?} decreases at {}", curve, i);
+                previous = value;
+            }
+            assert!(curve.apply(DVec3::ZERO, 4.0).x < 0.01);
+        }
+        assert_eq!(
+            ToneCurve::Reinhard.apply(DVec3::ONE, 4.0),
+            DVec3::splat(0.5)
+        );
+        assert!(
+            (ToneCurve::ExtendedReinhard.apply(DVec3::splat(4.0), 4.0) - DVec3::ONE).length()
+                < 1e-12
+        );
+    }
+
+    #[test]
+    fn test_exposure() {
+        let tone_map = ToneMap {
+            exposure: 1.0,
+            ..ToneMap::default()
+        };
+        assert_eq!(tone_map.tone_map(DVec3::splat(0.25)), DVec3::splat(0.5));
+        assert_eq!(tone_map.tone_map(DVec3::ONE), DVec3::ONE);
+    }
+
+    #[test]
+    fn test_dither_averages_out() {
+        let tone_map = ToneMap {
+            dither: true,
+            ..ToneMap::default()
+        };
+        // the middle of level 1 of a 2 bit image, in linear terms
+        let gray = DVec3::splat(((0.375 + 0.055) / 1.055_f64).powf(2.4));
+        let values: Vec<u32> = (0..1000)
+            .map(|index| tone_map.quantize(gray, index, 3)[0])
+            .collect();
+        assert!(values.iter().any(|value| *value != 1));
+        let mean = values.iter().sum::<u32>() as f64 / values.len() as f64;
+        assert!((mean - 1.0).abs() < 0.05, "mean {}", mean);
+        assert_eq!(tone_map.quantize(gray, 7, 3), tone_map.quantize(gray, 7, 3));
+
+        assert_eq!(ToneMap::default().quantize(gray, 7, 3), [1; 3]);
+    }
+}